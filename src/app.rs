@@ -1,23 +1,76 @@
 use std::{
-    collections::{BTreeMap, HashMap, HashSet},
-    path::Path,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
 };
 
 use egui::{Align2, CollapsingHeader, FontId, Pos2, Rect, Stroke, TextStyle, Vec2};
-use rangemap::RangeSet;
-use trace_explorer::trace::{Bio, Syscall, SyscallKind, SyscallStats};
+use itertools::Itertools;
+use crate::grid::Grid;
+use trace_explorer::{
+    data::{RangeStats, TraceData},
+    error::Result,
+    filter::{self, Event, Filter},
+    flame::{Frame, Weight},
+    stats::Histogram,
+    summary::{Bin, Summary},
+    trace::{Dev, Inode, Syscall, SyscallKind},
+};
 
-struct OnScreenBio {
-    bio: Bio,
-    rect: Rect,
+/// Zoomed out further than this many ns per pixel, traces are drawn from
+/// their summaries instead of event by event.
+const SUMMARY_NS_PER_PIXEL: f32 = 100_000.;
+const SUMMARY_BAND_HEIGHT: f32 = 100.;
+/// How opaque events that do not match the filter are drawn.
+const UNMATCHED_OPACITY: f32 = 0.15;
+const FLAME_ROW_HEIGHT: f32 = 18.;
+const HISTOGRAM_WIDTH: f32 = 160.;
+/// Shift-drags narrower than this are clicks rather than time ranges.
+const MIN_RANGE_WIDTH: f32 = 4.;
+/// The most events of a histogram bucket that are listed.
+const MAX_LISTED_EVENTS: usize = 1000;
+
+/// A trace drawn from its summaries as density bars, one per column of
+/// pixels. The summaries cover the events the detailed view would show.
+struct SummaryView {
+    syscall_top: f32,
+    bio_top: f32,
+    height: f32,
+    /// Syscalls and bios of each column.
+    columns: Vec<(Bin, Bin)>,
+    max_syscalls: u64,
+    max_bios: u64,
 }
 
-#[derive(Debug, Clone)]
-struct OnScreenSyscall {
-    syscall: Syscall,
-    rect: Rect,
+/// Whether each bio and syscall of a trace matches the filter.
+struct FilterMatches {
+    bios: Vec<bool>,
+    syscalls: Vec<bool>,
+    count: usize,
+}
+
+/// A file the syscalls are on: its inode where that is known, otherwise the
+/// descriptor, which only names a file within a process.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum FileKey {
+    Inode(Inode),
+    Fd { pid: u64, fd: i32 },
+}
+
+impl FileKey {
+    fn of(syscall: &Syscall) -> Option<Self> {
+        let file = syscall.file.as_ref()?;
+        Some(match &file.inode {
+            Some(inode) => FileKey::Inode(inode.clone()),
+            None => FileKey::Fd {
+                pid: syscall.pid,
+                fd: file.fd,
+            },
+        })
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 enum EventIndex {
     Bio(usize),
     Syscall(usize),
@@ -25,16 +78,55 @@ enum EventIndex {
 
 struct Trace {
     name: String,
-    bio_list: Vec<Bio>,
-    syscall_list: Vec<Syscall>,
-    head_map: BTreeMap<i64, EventIndex>,
-    tail_map: BTreeMap<i64, EventIndex>,
-    on_screen_bio: Vec<(usize, OnScreenBio)>,
-    on_screen_syscall: Vec<(usize, OnScreenSyscall)>,
+    data: TraceData,
+    /// Bios in the visible time range and where they are drawn, by index
+    /// into the bios of `data`.
+    on_screen_bio: Vec<(usize, Rect)>,
+    /// Syscalls in the visible time range and where they are drawn, by index
+    /// into the syscalls of `data`.
+    on_screen_syscall: Vec<(usize, Rect)>,
     selected_bio: Option<usize>,
     selected_syscall: Option<usize>,
-    stack_traces: Vec<Vec<(String, String)>>,
     time_origin: i64,
+    /// Devices the bios go to, in lane order.
+    devs: Vec<Dev>,
+    hidden_devs: HashSet<Dev>,
+    collapsed_devs: HashSet<Dev>,
+    /// The top of each bio lane on screen.
+    dev_lanes: Vec<(Dev, f32)>,
+    /// The top of each syscall lane on screen, with the thread's process
+    /// name.
+    thread_lanes: Vec<(u64, String, f32)>,
+    collapsed_threads: HashSet<u64>,
+    /// Total time spent in syscalls, by thread.
+    thread_time: HashMap<u64, i64>,
+    /// Put the threads that spend the most time in syscalls first.
+    sort_threads_by_time: bool,
+    /// The top of the trace on screen.
+    top: f32,
+    /// Summaries of the syscalls and bios shown, and whether those that do
+    /// not match the filter were left out. Built when first drawn after the
+    /// filters change.
+    summaries: Option<(bool, Summary, Summary)>,
+    /// Set instead of the on-screen events when zoomed out.
+    summary_view: Option<SummaryView>,
+    /// The latency statistics last shown, with the time range they are of,
+    /// or `None` for the whole trace.
+    latency_stats: Option<(Option<(i64, i64)>, RangeStats)>,
+    /// The histogram bucket whose events are listed: whether it is of bios,
+    /// the name of its group and the bucket.
+    selected_bucket: Option<(bool, &'static str, usize)>,
+    /// Files the syscalls are on, with their names, by name.
+    files: Vec<(FileKey, String)>,
+    /// Only show the syscalls on this file.
+    file_filter: Option<FileKey>,
+    /// Names of the processes in the trace, by pid.
+    processes: BTreeMap<u64, String>,
+    hidden_processes: HashSet<u64>,
+    /// Set when there is a filter expression.
+    filter_matches: Option<FilterMatches>,
+    /// The last flame graph drawn, with the time range and weight it is of.
+    flame: Option<((i64, i64, Weight), Frame)>,
 }
 
 impl Trace {
@@ -51,26 +143,30 @@ impl Trace {
             ui.separator();
             ui.heading(&self.name);
             ui.heading("Selected bio");
-            let bio = &self.bio_list[selected_bio];
+            let bio = &self.data.bios()[selected_bio];
             let latency = bio.end.unwrap_or(bio.start) - bio.start;
 
             ui.label(format!(
-                "Selected bio:\noffset:{} sectors\nsize:{} sectors\nlatency: {} ns",
-                bio.offset, bio.size, latency
+                "Selected bio:\ndevice: {}\noffset:{} sectors\nsize:{} sectors\nlatency: {} ns",
+                bio.dev, bio.offset, bio.size, latency
+            ));
+            ui.label(format!(
+                "process: {} ({})\nthread: {}\nCPU: {}",
+                bio.comm, bio.pid, bio.tid, bio.cpu
             ));
+            for (from, to) in bio.phases().iter().tuple_windows() {
+                ui.label(format!("{} → {}: {} ns", from.0, to.0, to.1 - from.1));
+            }
             CollapsingHeader::new("stack trace")
                 .id_salt(&self.name)
                 .show(ui, |ui| {
                     ui.label(format!("{}", bio.stack_trace));
-                    for (function, line) in self.stack_traces[bio.stack_trace].iter() {
-                        let frame = ui.button(function).on_hover_text(line);
-                        if frame.clicked() {
-                            println!("{}\t{}", function, line);
-                        }
+                    for (function, line) in self.data.stack_trace(bio) {
+                        ui.label(function).on_hover_text(line);
                     }
                 });
             if let Some(syscall) = self.selected_syscall {
-                let syscall = &self.syscall_list[syscall];
+                let syscall = &self.data.syscalls()[syscall];
                 ui.label(format!(
                     "From syscall start: {} ns",
                     bio.start - syscall.start
@@ -93,17 +189,25 @@ impl Trace {
             ui.separator();
             ui.heading(&self.name);
             ui.heading("Selected syscall");
-            let syscall = &mut self.syscall_list[selected_syscall];
+            self.data.syscall_stats(selected_syscall);
+            let syscall = &self.data.syscalls()[selected_syscall];
             ui.label(format!(
                 "Selected syscall:\nkind:{:?}\nlatency:{}",
                 syscall.kind,
                 syscall.end.unwrap_or(syscall.start) - syscall.start,
             ));
-            Self::analyze_selected_syscall(&self.head_map, &self.tail_map, &self.bio_list, syscall);
+            ui.label(format!(
+                "process: {} ({})\nthread: {}\nCPU: {}",
+                syscall.comm, syscall.pid, syscall.tid, syscall.cpu
+            ));
+            if let Some(file) = &syscall.file {
+                ui.label(format!("file: {}\nfd: {}", file, file.fd));
+            }
             let stats = syscall.stats.as_ref().unwrap();
             ui.label(format!(
-                "Write sectors: {}\nFlushes: {}\nIO time: {:.2}%",
+                "Write sectors: {}\nRead sectors: {}\nFlushes: {}\nIO time: {:.2}%",
                 stats.write_sectors,
+                stats.read_sectors,
                 stats.flushes,
                 stats.frac_io_time * 100.
             ));
@@ -119,120 +223,559 @@ impl Trace {
         None
     }
 
-    fn new(name: String, bio_json: &Path, stack_trace_csv: &Path, syscall_csv: &Path) -> Self {
-        // Read the bios
-        let bio_file = std::fs::File::open(bio_json).unwrap();
-        let bio_list: Vec<Bio> = serde_json::from_reader(bio_file).unwrap();
-        let mut head_map: BTreeMap<i64, EventIndex> = bio_list
-            .iter()
-            .enumerate()
-            .map(|(i, bio)| (bio.start, EventIndex::Bio(i)))
-            .collect();
-        let mut tail_map: BTreeMap<i64, EventIndex> = bio_list
-            .iter()
-            .enumerate()
-            .map(|(i, bio)| (bio.end.unwrap_or(bio.start), EventIndex::Bio(i)))
-            .collect();
+    /// Compares the latency distributions of the kinds of bios and syscalls
+    /// in `range`, relative to the time origin, or in the whole trace.
+    /// Clicking a histogram bucket lists its events; returns the time to
+    /// jump to when one of those is clicked.
+    fn latency_panel(&mut self, ui: &mut egui::Ui, range: Option<(i64, i64)>) -> Option<i64> {
+        let range = range.map(|(start, end)| (self.abs_time(start), self.abs_time(end)));
+        if self.latency_stats.as_ref().is_none_or(|(r, _)| *r != range) {
+            self.latency_stats = Some((range, self.data.range_stats(range)));
+        }
+        let stats = &self.latency_stats.as_ref().unwrap().1;
+        let mut selected_bucket = self.selected_bucket;
+        let mut jump = None;
+        CollapsingHeader::new(format!("{} latency", self.name))
+            .id_salt((&self.name, "latency"))
+            .show(ui, |ui| {
+                for (bios, groups) in [(true, &stats.bios), (false, &stats.syscalls)] {
+                    // Share the buckets, so that the histograms line up.
+                    let buckets = groups
+                        .values()
+                        .map(|group| group.histogram.occupied())
+                        .reduce(|a, b| a.start.min(b.start)..a.end.max(b.end))
+                        .unwrap_or(0..0);
+                    egui::Grid::new((&self.name, "latency grid", bios))
+                        .striped(true)
+                        .show(ui, |ui| {
+                            let title = if bios { "bio µs" } else { "syscall µs" };
+                            for heading in [
+                                title, "count", "mean", "p50", "p90", "p99", "p99.9", "max", "",
+                            ] {
+                                ui.strong(heading);
+                            }
+                            ui.end_row();
+                            let us = |ns: i64| format!("{:.1}", ns as f64 / 1000.);
+                            for (name, group) in groups {
+                                let summary = &group.summary;
+                                ui.label(*name);
+                                ui.label(summary.count.to_string());
+                                ui.label(format!("{:.1}", summary.mean / 1000.));
+                                ui.label(us(summary.p50));
+                                ui.label(us(summary.p90));
+                                ui.label(us(summary.p99));
+                                ui.label(us(summary.p999));
+                                ui.label(us(summary.max));
+                                let selected = selected_bucket
+                                    .filter(|&(b, n, _)| b == bios && n == *name)
+                                    .map(|(_, _, bucket)| bucket);
+                                let clicked =
+                                    draw_histogram(ui, &group.histogram, buckets.clone(), selected);
+                                if let Some(bucket) = clicked {
+                                    // Clicking the listed bucket again hides the list.
+                                    selected_bucket =
+                                        (selected != Some(bucket)).then_some((bios, *name, bucket));
+                                }
+                                ui.end_row();
+                            }
+                        });
+                }
 
-        // Load stack traces
-        let file = std::fs::File::open(stack_trace_csv).unwrap();
-        let mut reader = csv::ReaderBuilder::new()
-            .has_headers(false)
-            .flexible(true)
-            .from_reader(file);
-        let mut stack_traces: Vec<Vec<(String, String)>> = Vec::new();
-        for result in reader.records() {
-            let record = result.unwrap();
-            let stack_trace: Vec<(String, String)> = record[1]
-                .split('\n')
-                .map(|s| {
-                    let mut i = s.split("\t");
-                    (i.next().unwrap().to_owned(), i.next().unwrap().to_owned())
-                })
-                .collect();
-            stack_traces.push(stack_trace);
+                let Some((bios, name, bucket)) = selected_bucket else {
+                    return;
+                };
+                let group = if bios {
+                    stats.bios.get(name)
+                } else {
+                    stats.syscalls.get(name)
+                };
+                let Some(group) = group else {
+                    return;
+                };
+                let events: Vec<(usize, i64)> = group
+                    .events
+                    .iter()
+                    .copied()
+                    .filter(|&(_, latency)| Histogram::bucket(latency) == bucket)
+                    .collect();
+                let (low, high) = Histogram::bounds(bucket);
+                ui.label(format!(
+                    "{} {} {} from {} to {}",
+                    events.len(),
+                    name,
+                    if bios { "bios" } else { "syscalls" },
+                    format_ns(low),
+                    format_ns(high)
+                ));
+                egui::ScrollArea::vertical()
+                    .id_salt((&self.name, "bucket events"))
+                    .max_height(200.)
+                    .show(ui, |ui| {
+                        for (idx, latency) in events.into_iter().take(MAX_LISTED_EVENTS) {
+                            let (start, event) = if bios {
+                                (self.data.bios()[idx].start, EventIndex::Bio(idx))
+                            } else {
+                                (self.data.syscalls()[idx].start, EventIndex::Syscall(idx))
+                            };
+                            let label = format!(
+                                "{} at {}",
+                                format_ns(latency),
+                                format_ns(self.rel_time(start))
+                            );
+                            if ui.button(label).clicked() {
+                                jump = Some(event);
+                            }
+                        }
+                    });
+            });
+        self.selected_bucket = selected_bucket;
+        match jump? {
+            EventIndex::Bio(idx) => {
+                self.selected_bio = Some(idx);
+                Some(self.rel_time(self.data.bios()[idx].start))
+            }
+            EventIndex::Syscall(idx) => {
+                self.selected_syscall = Some(idx);
+                Some(self.rel_time(self.data.syscalls()[idx].start))
+            }
         }
+    }
 
-        // Load syscalls
-        let syscall_file = std::fs::File::open(syscall_csv).unwrap();
-        let syscall_list: Vec<Syscall> = serde_json::from_reader(syscall_file).unwrap();
+    /// Picks the file to show the syscalls of. Returns whether it changed.
+    fn file_filter_panel(&mut self, ui: &mut egui::Ui) -> bool {
+        if self.files.is_empty() {
+            return false;
+        }
+        let before = self.file_filter.clone();
+        let selected = self
+            .files
+            .iter()
+            .find(|(key, _)| Some(key) == self.file_filter.as_ref())
+            .map_or("All files", |(_, name)| name);
+        egui::ComboBox::from_id_salt((&self.name, "file filter"))
+            .selected_text(selected)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.file_filter, None, "All files");
+                for (key, name) in &self.files {
+                    ui.selectable_value(&mut self.file_filter, Some(key.clone()), name);
+                }
+            });
+        self.file_filter != before
+    }
 
-        head_map.extend(
-            syscall_list
-                .iter()
-                .enumerate()
-                .map(|(i, syscall)| (syscall.start, EventIndex::Syscall(i))),
-        );
+    /// Lists the processes with their colors, for hiding the events of
+    /// some. Returns whether any were hidden or shown.
+    fn process_panel(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        CollapsingHeader::new(format!("{} processes", self.name))
+            .id_salt((&self.name, "processes"))
+            .show(ui, |ui| {
+                for (pid, comm) in &self.processes {
+                    ui.horizontal(|ui| {
+                        let (rect, _) =
+                            ui.allocate_exact_size(Vec2::splat(10.), egui::Sense::hover());
+                        ui.painter().rect_filled(rect, 0., key_color(comm));
+                        let mut shown = !self.hidden_processes.contains(pid);
+                        if ui
+                            .checkbox(&mut shown, format!("{} ({})", comm, pid))
+                            .changed()
+                        {
+                            if shown {
+                                self.hidden_processes.remove(pid);
+                            } else {
+                                self.hidden_processes.insert(*pid);
+                            }
+                            changed = true;
+                        }
+                    });
+                }
+            });
+        changed
+    }
 
-        tail_map.extend(
-            syscall_list
-                .iter()
-                .enumerate()
-                .map(|(i, syscall)| (syscall.end.unwrap_or(syscall.start), EventIndex::Syscall(i))),
-        );
+    /// Shows how the syscall lanes are ordered and which are collapsed.
+    /// Returns whether anything changed.
+    fn thread_panel(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        CollapsingHeader::new(format!("{} threads", self.name))
+            .id_salt((&self.name, "threads"))
+            .show(ui, |ui| {
+                changed |= ui
+                    .checkbox(&mut self.sort_threads_by_time, "Sort by syscall time")
+                    .changed();
+                let mut threads: Vec<_> = self.thread_time.iter().collect();
+                threads.sort_by_key(|&(tid, time)| (-time, *tid));
+                for (tid, time) in threads {
+                    ui.horizontal(|ui| {
+                        let mut collapsed = self.collapsed_threads.contains(tid);
+                        if ui.toggle_value(&mut collapsed, "collapse").changed() {
+                            if collapsed {
+                                self.collapsed_threads.insert(*tid);
+                            } else {
+                                self.collapsed_threads.remove(tid);
+                            }
+                            changed = true;
+                        }
+                        ui.label(format!("{}: {:.3} ms", tid, *time as f64 / 1e6));
+                    });
+                }
+            });
+        changed
+    }
 
-        let time_origin = syscall_list[0].start;
+    /// Shows which device lanes are visible and collapsed. Returns whether
+    /// anything changed.
+    fn lane_panel(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        CollapsingHeader::new(format!("{} devices", self.name))
+            .id_salt((&self.name, "devices"))
+            .show(ui, |ui| {
+                for dev in &self.devs {
+                    ui.horizontal(|ui| {
+                        let mut shown = !self.hidden_devs.contains(dev);
+                        if ui.checkbox(&mut shown, dev.to_string()).changed() {
+                            if shown {
+                                self.hidden_devs.remove(dev);
+                            } else {
+                                self.hidden_devs.insert(dev.clone());
+                            }
+                            changed = true;
+                        }
+                        let mut collapsed = self.collapsed_devs.contains(dev);
+                        if ui.toggle_value(&mut collapsed, "collapse").changed() {
+                            if collapsed {
+                                self.collapsed_devs.insert(dev.clone());
+                            } else {
+                                self.collapsed_devs.remove(dev);
+                            }
+                            changed = true;
+                        }
+                    });
+                }
+            });
+        changed
+    }
+
+    fn new(name: String, data: TraceData) -> Self {
+        let bio_list = data.bios();
+        let syscall_list = data.syscalls();
+        let time_origin = data.time_origin();
+
+        let devs: BTreeSet<Dev> = bio_list.iter().map(|bio| bio.dev.clone()).collect();
+        let files: HashMap<FileKey, String> = syscall_list
+            .iter()
+            .filter_map(|syscall| {
+                let file = syscall.file.as_ref()?;
+                let name = match file.inode {
+                    Some(_) => file.to_string(),
+                    None => format!("{} of pid {}", file, syscall.pid),
+                };
+                Some((FileKey::of(syscall)?, name))
+            })
+            .collect();
+        let mut files: Vec<(FileKey, String)> = files.into_iter().collect();
+        files.sort_by(|(a_key, a_name), (b_key, b_name)| (a_name, a_key).cmp(&(b_name, b_key)));
+        let mut thread_time: HashMap<u64, i64> = HashMap::new();
+        for syscall in syscall_list {
+            *thread_time.entry(syscall.tid).or_default() +=
+                syscall.end.unwrap_or(syscall.start) - syscall.start;
+        }
+        let processes: BTreeMap<u64, String> = bio_list
+            .iter()
+            .map(|bio| (bio.pid, bio.comm.clone()))
+            .chain(
+                syscall_list
+                    .iter()
+                    .map(|syscall| (syscall.pid, syscall.comm.clone())),
+            )
+            .collect();
 
         Self {
-            bio_list,
-            head_map,
-            tail_map,
+            data,
             on_screen_bio: Vec::new(),
             selected_bio: None,
-            stack_traces,
             time_origin,
             name,
-            syscall_list,
             on_screen_syscall: Vec::new(),
             selected_syscall: None,
+            devs: devs.into_iter().collect(),
+            hidden_devs: HashSet::new(),
+            collapsed_devs: HashSet::new(),
+            dev_lanes: Vec::new(),
+            thread_lanes: Vec::new(),
+            collapsed_threads: HashSet::new(),
+            thread_time,
+            sort_threads_by_time: false,
+            top: 0.,
+            summaries: None,
+            summary_view: None,
+            latency_stats: None,
+            selected_bucket: None,
+            files,
+            file_filter: None,
+            processes,
+            hidden_processes: HashSet::new(),
+            filter_matches: None,
+            flame: None,
+        }
+    }
+
+    /// Loads the trace in `dir`, as written by `trace-process`.
+    fn open(dir: &Path) -> Result<Self> {
+        let name = dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| dir.display().to_string());
+        Ok(Self::new(name, TraceData::load(dir)?))
+    }
+
+    /// Matches every event against `filter`, or clears the matches.
+    fn apply_filter(&mut self, filter: Option<&Filter>) {
+        self.summaries = None;
+        self.filter_matches = filter.map(|filter| {
+            let bios: Vec<bool> = self
+                .data
+                .bios()
+                .iter()
+                .map(|bio| filter.matches(Event::Bio(bio, self.data.stack_trace(bio))))
+                .collect();
+            let syscalls: Vec<bool> = self
+                .data
+                .syscalls()
+                .iter()
+                .map(|syscall| filter.matches(Event::Syscall(syscall)))
+                .collect();
+            let count = bios.iter().chain(&syscalls).filter(|&&m| m).count();
+            FilterMatches {
+                bios,
+                syscalls,
+                count,
+            }
+        });
+    }
+
+    /// Whether the bio at `idx` is shown, leaving out those that do not
+    /// match the filter if `hide_unmatched`.
+    fn bio_shown(&self, idx: usize, hide_unmatched: bool) -> bool {
+        let bio = &self.data.bios()[idx];
+        !self.hidden_devs.contains(&bio.dev)
+            && !self.hidden_processes.contains(&bio.pid)
+            && (!hide_unmatched || self.bio_matches(idx))
+    }
+
+    /// Whether the syscall at `idx` is shown, leaving out those that do not
+    /// match the filter if `hide_unmatched`.
+    fn syscall_shown(&self, idx: usize, hide_unmatched: bool) -> bool {
+        let syscall = &self.data.syscalls()[idx];
+        !self.hidden_processes.contains(&syscall.pid)
+            && (!hide_unmatched || self.syscall_matches(idx))
+            && self
+                .file_filter
+                .as_ref()
+                .is_none_or(|filter| FileKey::of(syscall).as_ref() == Some(filter))
+    }
+
+    fn bio_matches(&self, idx: usize) -> bool {
+        self.filter_matches.as_ref().is_none_or(|m| m.bios[idx])
+    }
+
+    fn syscall_matches(&self, idx: usize) -> bool {
+        self.filter_matches.as_ref().is_none_or(|m| m.syscalls[idx])
+    }
+
+    /// The events that match the filter, with their relative start times.
+    fn match_starts(&self) -> impl Iterator<Item = (i64, EventIndex)> + '_ {
+        let matches = self.filter_matches.as_ref();
+        let bios = matches
+            .into_iter()
+            .flat_map(|m| m.bios.iter().positions(|&m| m))
+            .map(|idx| (self.data.bios()[idx].start, EventIndex::Bio(idx)));
+        let syscalls = matches
+            .into_iter()
+            .flat_map(|m| m.syscalls.iter().positions(|&m| m))
+            .map(|idx| (self.data.syscalls()[idx].start, EventIndex::Syscall(idx)));
+        bios.chain(syscalls)
+            .map(|(time, event)| (self.rel_time(time), event))
+    }
+
+    /// The time range of the flame graph of this trace: `range`, relative to
+    /// the time origin, or else the selected syscall.
+    fn flame_range(&self, range: Option<(i64, i64)>) -> Option<(i64, i64)> {
+        match range {
+            Some((start, end)) => Some((self.abs_time(start), self.abs_time(end))),
+            None => {
+                let syscall = &self.data.syscalls()[self.selected_syscall?];
+                Some((syscall.start, syscall.end.unwrap_or(syscall.start)))
+            }
+        }
+    }
+
+    /// Merges the stack traces of the bios that overlap `start..=end`,
+    /// reusing the last flame graph if it is of the same bios and weight.
+    fn flame_graph(&mut self, start: i64, end: i64, weight: Weight) -> &Frame {
+        let key = (start, end, weight);
+        if self.flame.as_ref().is_none_or(|(k, _)| *k != key) {
+            let stacks = self.data.bios_in(start, end).map(|idx| {
+                let bio = &self.data.bios()[idx];
+                (self.data.stack_trace(bio), weight.of(bio))
+            });
+            self.flame = Some((key, Frame::new(self.name.clone(), stacks)));
         }
+        &self.flame.as_ref().unwrap().1
     }
 
-    fn refresh_on_screen(&mut self, rel_time: i64, duration: i64) {
+    /// Finds the events in the time range, leaving out those that are
+    /// hidden, and those that do not match the filter if `hide_unmatched`.
+    fn refresh_on_screen(&mut self, rel_time: i64, duration: i64, hide_unmatched: bool) {
         self.on_screen_bio.clear();
         self.on_screen_syscall.clear();
         let start = self.abs_time(rel_time);
         let end = start + duration;
 
-        for map in [&self.head_map, &self.tail_map] {
-            for (_, idx) in map.range(start..=end) {
-                match idx {
-                    EventIndex::Bio(idx) => {
-                        let bio = self.bio_list[*idx].clone();
-                        self.on_screen_bio.push((
-                            *idx,
-                            OnScreenBio {
-                                bio,
-                                rect: Rect::NOTHING,
-                            },
-                        ));
-                    }
-                    EventIndex::Syscall(idx) => {
-                        let syscall = self.syscall_list[*idx].clone();
-                        self.on_screen_syscall.push((
-                            *idx,
-                            OnScreenSyscall {
-                                syscall,
-                                rect: Rect::NOTHING,
-                            },
-                        ));
-                    }
-                }
+        for idx in self.data.bios_in(start, end) {
+            if self.bio_shown(idx, hide_unmatched) {
+                self.on_screen_bio.push((idx, Rect::NOTHING));
+            }
+        }
+        for idx in self.data.syscalls_in(start, end) {
+            if self.syscall_shown(idx, hide_unmatched) {
+                self.on_screen_syscall.push((idx, Rect::NOTHING));
             }
         }
     }
 
+    /// Summarizes the events that are shown, unless that has been done since
+    /// the filters last changed.
+    fn refresh_summaries(&mut self, hide_unmatched: bool) {
+        if self
+            .summaries
+            .as_ref()
+            .is_some_and(|(hidden, _, _)| *hidden == hide_unmatched)
+        {
+            return;
+        }
+        let bios = self.data.bios().iter().enumerate();
+        let bio_summary = Summary::new(
+            bios.filter(|&(idx, _)| self.bio_shown(idx, hide_unmatched))
+                .map(|(_, bio)| (bio.start, bio.end.unwrap_or(bio.start), bio.size * 512)),
+        );
+        let syscalls = self.data.syscalls().iter().enumerate();
+        let syscall_summary = Summary::new(
+            syscalls
+                .filter(|&(idx, _)| self.syscall_shown(idx, hide_unmatched))
+                .map(|(_, syscall)| {
+                    let end = syscall.end.unwrap_or(syscall.start);
+                    (syscall.start, end, syscall.kind.bytes().unwrap_or(0))
+                }),
+        );
+        self.summaries = Some((hide_unmatched, syscall_summary, bio_summary));
+    }
+
+    /// Lays out the summaries of the `width` pixels from `rel_time` on,
+    /// leaving out the events that do not match the filter if
+    /// `hide_unmatched`.
+    fn layout_summary(
+        &mut self,
+        last_y: &mut f32,
+        rel_time: i64,
+        zoom: f32,
+        width: f32,
+        hide_unmatched: bool,
+    ) {
+        self.refresh_summaries(hide_unmatched);
+        let (_, syscall_summary, bio_summary) = self.summaries.as_ref().unwrap();
+        self.on_screen_bio.clear();
+        self.on_screen_syscall.clear();
+        self.thread_lanes.clear();
+        self.dev_lanes.clear();
+
+        self.top = *last_y;
+        // Leave room for the name of the trace.
+        *last_y += 30.;
+
+        let start = self.abs_time(rel_time);
+        let ns_per_pixel = (1. / zoom) as i64;
+        let columns: Vec<(Bin, Bin)> = (0..width.ceil() as i64)
+            .map(|x| {
+                let from = start + x * ns_per_pixel;
+                let to = from + ns_per_pixel;
+                (
+                    syscall_summary.query(from, to, ns_per_pixel),
+                    bio_summary.query(from, to, ns_per_pixel),
+                )
+            })
+            .collect();
+        let syscall_top = *last_y;
+        let bio_top = syscall_top + SUMMARY_BAND_HEIGHT + 30.;
+        *last_y = bio_top + SUMMARY_BAND_HEIGHT;
+        self.summary_view = Some(SummaryView {
+            syscall_top,
+            bio_top,
+            height: SUMMARY_BAND_HEIGHT,
+            max_syscalls: columns.iter().map(|(s, _)| s.count).max().unwrap_or(0),
+            max_bios: columns.iter().map(|(_, b)| b.count).max().unwrap_or(0),
+            columns,
+        });
+    }
+
     fn layout(&mut self, last_y: &mut f32, rel_time: i64, zoom: f32) {
         let curr_time = self.abs_time(rel_time);
+        self.summary_view = None;
+
+        self.top = *last_y;
+        // Leave room for the name of the trace.
+        *last_y += 30.;
+
+        // One lane per thread, each sorted by start.
+        let thread_time = &self.thread_time;
+        let sort_by_time = self.sort_threads_by_time;
+        let syscall_list = self.data.syscalls();
+        self.on_screen_syscall.sort_by_key(|(idx, _)| {
+            let syscall = &syscall_list[*idx];
+            let time = if sort_by_time {
+                -thread_time[&syscall.tid]
+            } else {
+                0
+            };
+            (time, syscall.tid, syscall.start)
+        });
 
-        for (_i, syscall) in self.on_screen_syscall.iter_mut() {
-            let x = (syscall.syscall.start - curr_time) as f32 * zoom;
-            let y = *last_y;
-            let width = (syscall.syscall.end.unwrap_or(syscall.syscall.start)
-                - syscall.syscall.start) as f32
-                * zoom;
-            let height = 50.0;
-            syscall.rect = Rect::from_min_size(
+        self.thread_lanes.clear();
+        // When each row of the current lane becomes free; a thread only has
+        // more than one row if the end of a syscall got lost.
+        let mut row_ends: Vec<i64> = Vec::new();
+        let mut collapsed = false;
+        let mut height = 50.;
+        for (idx, rect) in self.on_screen_syscall.iter_mut() {
+            let syscall = &self.data.syscalls()[*idx];
+            let tid = syscall.tid;
+            if self.thread_lanes.last().map(|(tid, _, _)| *tid) != Some(tid) {
+                if !self.thread_lanes.is_empty() {
+                    *last_y += row_ends.len() as f32 * height + 10.;
+                }
+                self.thread_lanes
+                    .push((tid, syscall.comm.clone(), *last_y));
+                row_ends.clear();
+                collapsed = self.collapsed_threads.contains(&tid);
+                height = if collapsed { 10. } else { 50. };
+            }
+            let start = syscall.start;
+            let end = syscall.end.unwrap_or(start);
+            let free_row = if collapsed && !row_ends.is_empty() {
+                Some(0)
+            } else {
+                row_ends.iter().position(|&row_end| row_end <= start)
+            };
+            let row = free_row.unwrap_or_else(|| {
+                row_ends.push(end);
+                row_ends.len() - 1
+            });
+            row_ends[row] = row_ends[row].max(end);
+
+            let x = (start - curr_time) as f32 * zoom;
+            let y = *last_y + row as f32 * height;
+            let width = (end - start) as f32 * zoom;
+            *rect = Rect::from_min_size(
                 Pos2 { x, y },
                 Vec2 {
                     x: width,
@@ -240,33 +783,49 @@ impl Trace {
                 },
             );
         }
-        *last_y += 200.;
+        *last_y += row_ends.len() as f32 * height + 50.;
 
+        // One lane per device, each sorted by offset.
+        let bio_list = self.data.bios();
         self.on_screen_bio.sort_by(|a, b| {
-            let a = a.1.bio.offset;
-            let b = &b.1.bio.offset;
-            a.cmp(b)
+            let a = &bio_list[a.0];
+            let b = &bio_list[b.0];
+            (&a.dev, a.offset).cmp(&(&b.dev, b.offset))
         });
 
         let mut curr_y = *last_y;
         let mut _last_x = 0.0;
         let mut last_offset = 0;
-
-        for (_idx, on_screen_bio) in &mut self.on_screen_bio {
-            let x = (on_screen_bio.bio.start - curr_time) as f32 * zoom;
-            let mut height = 10.0 * on_screen_bio.bio.size as f32;
+        self.dev_lanes.clear();
+
+        for (idx, rect) in &mut self.on_screen_bio {
+            let bio = &self.data.bios()[*idx];
+            if self.dev_lanes.last().map(|(dev, _)| dev) != Some(&bio.dev) {
+                let lane_y = curr_y + 20.;
+                self.dev_lanes.push((bio.dev.clone(), lane_y));
+                // Leave room for the name of the device.
+                *last_y = lane_y + 30.;
+                curr_y = *last_y;
+                last_offset = 0;
+            }
+            let x = (bio.start - curr_time) as f32 * zoom;
+            let width = (bio.end.unwrap_or(bio.start) - bio.start) as f32 * zoom;
+            if self.collapsed_devs.contains(&bio.dev) {
+                // All bios of a collapsed lane go on a single row.
+                *rect = Rect::from_min_size(Pos2 { x, y: *last_y }, Vec2 { x: width, y: 7. });
+                curr_y = *last_y + 8.;
+                continue;
+            }
+            let mut height = 10.0 * bio.size as f32;
             if height < 10.0 {
                 height = 7.0
             }
-            let y = if last_offset >= on_screen_bio.bio.offset {
+            let y = if last_offset >= bio.offset {
                 *last_y
             } else {
                 curr_y
             };
-            let width = (on_screen_bio.bio.end.unwrap_or(on_screen_bio.bio.start)
-                - on_screen_bio.bio.start) as f32
-                * zoom;
-            on_screen_bio.rect = Rect::from_min_size(
+            *rect = Rect::from_min_size(
                 Pos2 { x, y },
                 Vec2 {
                     x: width,
@@ -274,70 +833,12 @@ impl Trace {
                 },
             );
             _last_x = x + width;
-            last_offset = on_screen_bio.bio.offset;
+            last_offset = bio.offset;
             *last_y = y;
             curr_y = y + height + 1.;
         }
         *last_y = curr_y;
     }
-
-    fn analyze_selected_syscall(
-        head_map: &BTreeMap<i64, EventIndex>,
-        tail_map: &BTreeMap<i64, EventIndex>,
-        bio_list: &[Bio],
-        syscall: &mut Syscall,
-    ) {
-        if let Some(stats) = &syscall.stats {
-            return;
-        }
-        let start = syscall.start;
-        let end = syscall.end.unwrap_or(start);
-        // use head_map and tail map to find all bios that are in the range of the selected syscall
-        let start_in_syscall: HashSet<usize> = head_map
-            .range(start..=end)
-            .filter_map(|(_, idx)| {
-                if let EventIndex::Bio(idx) = idx {
-                    Some(*idx)
-                } else {
-                    None
-                }
-            })
-            .collect();
-        let end_in_syscall: HashSet<usize> = tail_map
-            .range(start..=end)
-            .filter_map(|(_, idx)| {
-                if let EventIndex::Bio(idx) = idx {
-                    Some(*idx)
-                } else {
-                    None
-                }
-            })
-            .collect();
-        let mut stats = SyscallStats {
-            write_sectors: 0,
-            flushes: 0,
-            frac_io_time: 0.,
-        };
-
-        let mut io_range_set = RangeSet::new();
-
-        for i in start_in_syscall.intersection(&end_in_syscall) {
-            let bio = &bio_list[*i];
-            io_range_set.insert(bio.start..bio.end.unwrap_or(bio.start));
-            if bio.is_flush {
-                stats.flushes += 1;
-            }
-            if bio.is_write {
-                stats.write_sectors += bio.size;
-            }
-        }
-        stats.frac_io_time = io_range_set
-            .into_iter()
-            .map(|range| range.end - range.start)
-            .sum::<i64>() as f64
-            / (end - start) as f64;
-        syscall.stats = Some(stats);
-    }
 }
 
 pub struct TemplateApp {
@@ -348,32 +849,96 @@ pub struct TemplateApp {
     y_zoom: f32,
 
     rect: Rect,
+
+    /// Errors to show to the user, e.g. traces that failed to load.
+    errors: Vec<String>,
+
+    color_by: ColorBy,
+
+    /// Where the events on screen are drawn.
+    grid: Grid<(usize, EventIndex)>,
+
+    /// The filter expression as typed.
+    filter_text: String,
+    /// The last filter expression that parsed.
+    filter: Option<Filter>,
+    /// Why the filter expression as typed does not parse.
+    filter_error: Option<String>,
+    /// Leave out the events that do not match the filter, instead of dimming
+    /// them.
+    hide_unmatched: bool,
+    /// The match last jumped to, as its relative start time, trace and
+    /// event, so that the next jump goes on to the matches that start at
+    /// the same time.
+    last_match: Option<(i64, usize, EventIndex)>,
+
+    /// The time range selected by shift-dragging, relative to the time
+    /// origin.
+    time_range: Option<(i64, i64)>,
+    /// Where the drag selecting `time_range` started, as a time and an x.
+    range_drag: Option<(i64, f32)>,
+    show_flame: bool,
+    flame_weight: Weight,
+
+    /// Show the latencies of the events in view instead of the whole traces.
+    latency_of_viewport: bool,
+}
+
+/// What events are colored by.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ColorBy {
+    Kind,
+    File,
+    Process,
 }
 
 impl TemplateApp {
     /// Called once before the first frame.
-    pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
-        let traces = vec![
-            Trace::new(
-                "btrfs".to_string(),
-                Path::new("/home/mike/docs/wisc/os/project/p3/traces/btrfs/bio.json"),
-                Path::new("/home/mike/docs/wisc/os/project/p3/traces/btrfs/stack.csv"),
-                Path::new("/home/mike/docs/wisc/os/project/p3/traces/btrfs/syscall.json"),
-            ),
-            Trace::new(
-                "btrfs-2".to_string(),
-                Path::new("bio.json"),
-                Path::new("stack.csv"),
-                Path::new("syscall.json"),
-            ),
-        ];
-
-        Self {
+    pub fn new(_cc: &eframe::CreationContext<'_>, dirs: &[PathBuf]) -> Self {
+        let mut app = Self {
             zoom: 0.00001,
             curr_time: 0,
             rect: Rect::from_min_size(Pos2::ZERO, Vec2::new(0., 0.)),
-            traces,
+            traces: Vec::new(),
             y_zoom: 1.,
+            errors: Vec::new(),
+            color_by: ColorBy::Kind,
+            grid: Grid::new(32.),
+            filter_text: String::new(),
+            filter: None,
+            filter_error: None,
+            hide_unmatched: false,
+            last_match: None,
+            time_range: None,
+            range_drag: None,
+            show_flame: false,
+            flame_weight: Weight::Count,
+            latency_of_viewport: false,
+        };
+        for dir in dirs {
+            app.open(dir);
+        }
+        app
+    }
+
+    fn open(&mut self, path: &Path) {
+        // Accept one of the files of a trace as well as its directory.
+        let dir = if path.is_dir() {
+            path
+        } else {
+            path.parent().unwrap_or(path)
+        };
+        match Trace::open(dir) {
+            Ok(mut trace) => {
+                trace.apply_filter(self.filter.as_ref());
+                self.traces.push(trace);
+                self.layout();
+            }
+            Err(e) => {
+                log::error!("cannot open {}: {}", dir.display(), e);
+                self.errors
+                    .push(format!("Cannot open {}:\n{}", dir.display(), e));
+            }
         }
     }
 
@@ -388,6 +953,83 @@ impl TemplateApp {
         self.layout();
     }
 
+    /// Parses the filter expression and matches the events of every trace
+    /// against it. An expression that does not parse leaves the last filter
+    /// in place.
+    fn set_filter(&mut self) {
+        let text = self.filter_text.trim();
+        self.filter_error = None;
+        self.filter = if text.is_empty() {
+            None
+        } else {
+            match Filter::parse(text) {
+                Ok(filter) => Some(filter),
+                Err(e) => {
+                    self.filter_error = Some(e.to_string());
+                    return;
+                }
+            }
+        };
+        for trace in &mut self.traces {
+            trace.apply_filter(self.filter.as_ref());
+        }
+        self.layout();
+    }
+
+    /// Scrolls to the start of the next or previous event, in any trace,
+    /// that matches the filter.
+    fn jump_to_match(&mut self, forward: bool) {
+        let matches = self.traces.iter().enumerate().flat_map(|(t, trace)| {
+            trace
+                .match_starts()
+                .map(move |(time, event)| (time, t, event))
+        });
+        let from = match self.last_match {
+            Some(last) if last.0 == self.curr_time => last,
+            // Otherwise step from the left edge of the view, past the
+            // matches that start right at it.
+            _ if forward => (self.curr_time, usize::MAX, EventIndex::Syscall(usize::MAX)),
+            _ => (self.curr_time, 0, EventIndex::Bio(0)),
+        };
+        if let Some(found) = next_match(matches, from, forward) {
+            self.last_match = Some(found);
+            self.scroll_to(found.0);
+        }
+    }
+
+    fn filter_bar(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Filter:");
+            let edit = egui::TextEdit::singleline(&mut self.filter_text)
+                .hint_text("syscall.kind == fsync && latency > 5ms")
+                .desired_width(400.);
+            if ui.add(edit).changed() {
+                self.set_filter();
+            }
+            let mut changed = ui.radio_value(&mut self.hide_unmatched, false, "dim").changed();
+            changed |= ui.radio_value(&mut self.hide_unmatched, true, "hide").changed();
+            if changed {
+                self.layout();
+            }
+            if ui.button("◀").on_hover_text("Previous match").clicked() {
+                self.jump_to_match(false);
+            }
+            if ui.button("▶").on_hover_text("Next match").clicked() {
+                self.jump_to_match(true);
+            }
+            if let Some(error) = &self.filter_error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            } else if self.filter.is_some() {
+                let count: usize = self
+                    .traces
+                    .iter()
+                    .filter_map(|trace| Some(trace.filter_matches.as_ref()?.count))
+                    .sum();
+                ui.label(format!("{} matches", count));
+            }
+        });
+    }
+
     fn change_zoom(&mut self, factor: f32) {
         self.zoom *= factor;
         self.layout();
@@ -412,10 +1054,24 @@ impl TemplateApp {
 
     fn layout(&mut self) {
         let mut last_y = 0.0;
+        let summarize = 1. / self.zoom > SUMMARY_NS_PER_PIXEL;
         for trace in self.traces.iter_mut() {
-            trace.refresh_on_screen(self.curr_time, (self.rect.width() / self.zoom) as i64);
-
-            trace.layout(&mut last_y, self.curr_time, self.zoom);
+            if summarize {
+                trace.layout_summary(
+                    &mut last_y,
+                    self.curr_time,
+                    self.zoom,
+                    self.rect.width(),
+                    self.hide_unmatched,
+                );
+            } else {
+                trace.refresh_on_screen(
+                    self.curr_time,
+                    (self.rect.width() / self.zoom) as i64,
+                    self.hide_unmatched,
+                );
+                trace.layout(&mut last_y, self.curr_time, self.zoom);
+            }
             last_y += 50.;
         }
 
@@ -427,47 +1083,226 @@ impl TemplateApp {
                 for rect in trace
                     .on_screen_bio
                     .iter_mut()
-                    .map(|x| &mut x.1.rect)
-                    .chain(trace.on_screen_syscall.iter_mut().map(|x| &mut x.1.rect))
+                    .map(|x| &mut x.1)
+                    .chain(trace.on_screen_syscall.iter_mut().map(|x| &mut x.1))
                 {
                     rect.min.y /= self.y_zoom;
                     rect.max.y /= self.y_zoom;
                 }
+                for (_dev, y) in trace.dev_lanes.iter_mut() {
+                    *y /= self.y_zoom;
+                }
+                for (_tid, _comm, y) in trace.thread_lanes.iter_mut() {
+                    *y /= self.y_zoom;
+                }
+                trace.top /= self.y_zoom;
+                if let Some(view) = &mut trace.summary_view {
+                    view.syscall_top /= self.y_zoom;
+                    view.bio_top /= self.y_zoom;
+                    view.height /= self.y_zoom;
+                }
+            }
+        }
+
+        // Index the rects in the order they are drawn.
+        self.grid
+            .reset(Rect::from_min_size(Pos2::ZERO, self.rect.size()));
+        for (t, trace) in self.traces.iter().enumerate() {
+            for (idx, rect) in &trace.on_screen_syscall {
+                self.grid.insert(*rect, (t, EventIndex::Syscall(*idx)));
+            }
+            for (idx, rect) in &trace.on_screen_bio {
+                self.grid.insert(*rect, (t, EventIndex::Bio(*idx)));
             }
         }
     }
 
+    /// Finds the event drawn at `pos`, relative to the timeline, and the
+    /// index of its trace.
+    fn hit_test(&self, pos: Pos2) -> Option<(usize, EventIndex)> {
+        self.grid.hit(pos)
+    }
+
+    /// Finds the summary bar at `pos`, relative to the timeline.
+    fn summary_at(&self, pos: Pos2) -> Option<(&'static str, Bin)> {
+        self.traces.iter().find_map(|trace| {
+            let view = trace.summary_view.as_ref()?;
+            let (syscalls, bios) = view.columns.get(pos.x as usize)?;
+            if (view.syscall_top..view.syscall_top + view.height).contains(&pos.y) {
+                Some(("syscalls", *syscalls))
+            } else if (view.bio_top..view.bio_top + view.height).contains(&pos.y) {
+                Some(("bios", *bios))
+            } else {
+                None
+            }
+        })
+    }
+
     fn select(&mut self, pos: Pos2) {
-        for trace in self.traces.iter_mut() {
-            for (idx, on_screen_syscall) in trace.on_screen_syscall.iter_mut() {
-                let rect = &on_screen_syscall.rect;
-                if rect.contains(pos) {
-                    trace.selected_syscall = Some(*idx);
+        match self.hit_test(pos) {
+            Some((t, EventIndex::Bio(idx))) => self.traces[t].selected_bio = Some(idx),
+            Some((t, EventIndex::Syscall(idx))) => self.traces[t].selected_syscall = Some(idx),
+            None => {}
+        }
+    }
+
+    /// Shows the details of the event under the pointer.
+    fn tooltip(&mut self, ui: &mut egui::Ui, t: usize, event: EventIndex) {
+        let trace = &mut self.traces[t];
+        match event {
+            EventIndex::Bio(idx) => {
+                let bio = &trace.data.bios()[idx];
+                let flags: String = [
+                    if bio.is_write { "W" } else { "R" },
+                    if bio.is_flush { "F" } else { "" },
+                    if bio.is_metadata { "M" } else { "" },
+                ]
+                .concat();
+                ui.label(format!(
+                    "bio on {}\noffset: {:#x}\nsize: {} sectors\nflags: {}\nlatency: {}\nthread: {} {}",
+                    bio.dev,
+                    bio.offset,
+                    bio.size,
+                    flags,
+                    format_ns(bio.end.unwrap_or(bio.start) - bio.start),
+                    bio.comm,
+                    bio.tid,
+                ));
+                for (function, _) in trace.data.stack_trace(bio).iter().take(3) {
+                    ui.monospace(function);
                 }
             }
-            for (idx, on_screen_bio) in trace.on_screen_bio.iter() {
-                let rect = &on_screen_bio.rect;
-                if rect.contains(pos) {
-                    trace.selected_bio = Some(*idx);
+            EventIndex::Syscall(idx) => {
+                trace.data.syscall_stats(idx);
+                let syscall = &trace.data.syscalls()[idx];
+                ui.label(format!(
+                    "{}\nlatency: {}\nthread: {} {}",
+                    syscall.kind.name(),
+                    format_ns(syscall.end.unwrap_or(syscall.start) - syscall.start),
+                    syscall.comm,
+                    syscall.tid,
+                ));
+                if let Some(bytes) = syscall.kind.bytes() {
+                    ui.label(format!("bytes: {}", bytes));
+                }
+                if let Some(file) = &syscall.file {
+                    ui.label(format!("file: {}", file));
+                }
+                if let Some(stats) = &syscall.stats {
+                    ui.label(format!(
+                        "write sectors: {}\nread sectors: {}\nflushes: {}\nIO time: {:.2}%",
+                        stats.write_sectors,
+                        stats.read_sectors,
+                        stats.flushes,
+                        stats.frac_io_time * 100.
+                    ));
                 }
             }
         }
     }
 
-    fn input(&mut self, i: &egui::InputState) {
-        if i.key_pressed(egui::Key::L) {
-            self.scroll(50.);
+    /// The time, relative to the time origin, at the screen position `x`.
+    fn time_at(&self, x: f32) -> i64 {
+        self.curr_time + ((x - self.rect.min.x) / self.zoom) as i64
+    }
+
+    /// Shows a flame graph for each trace of the bios in the selected time
+    /// range or, without one, in the selected syscall. Clicking a frame
+    /// filters the bios to those under it, by their call path.
+    fn flame_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_flame;
+        let mut clicked = None;
+        egui::Window::new("Flame graph")
+            .open(&mut open)
+            .default_width(600.)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Weight:");
+                    ui.radio_value(&mut self.flame_weight, Weight::Count, "count");
+                    ui.radio_value(&mut self.flame_weight, Weight::Sectors, "sectors");
+                    ui.radio_value(&mut self.flame_weight, Weight::Latency, "latency");
+                    if self.time_range.is_some()
+                        && ui
+                            .button("Clear range")
+                            .on_hover_text("Or press Escape, or click on the timeline")
+                            .clicked()
+                    {
+                        self.time_range = None;
+                    }
+                });
+                if self.time_range.is_none()
+                    && self
+                        .traces
+                        .iter()
+                        .all(|trace| trace.selected_syscall.is_none())
+                {
+                    ui.label("Shift-drag on the timeline to select a range, or select a syscall.");
+                }
+                let weight = self.flame_weight;
+                for trace in &mut self.traces {
+                    let Some((start, end)) = trace.flame_range(self.time_range) else {
+                        continue;
+                    };
+                    ui.separator();
+                    ui.label(format!("{}: {}", trace.name, format_ns(end - start)));
+                    let root = trace.flame_graph(start, end, weight);
+                    if root.weight == 0 {
+                        ui.label("No bios");
+                    } else if let Some(path) = draw_flame_graph(ui, root, weight) {
+                        clicked = Some(path);
+                    }
+                }
+            });
+        self.show_flame = open;
+        if let Some(path) = clicked {
+            self.filter_text = format!("bio.stack under {}", filter::quote(&path.join(";")));
+            self.set_filter();
         }
-        if i.key_pressed(egui::Key::H) {
-            self.scroll(-50.);
+    }
+
+    /// Handles the keyboard shortcuts, unless `keys` is false because a text
+    /// field has the keyboard, and clicks, if `on_timeline` because no window
+    /// covers the timeline where the pointer is.
+    fn input(&mut self, i: &egui::InputState, keys: bool, on_timeline: bool) {
+        if keys {
+            if i.key_pressed(egui::Key::Escape) {
+                self.time_range = None;
+                self.range_drag = None;
+            }
+            if i.key_pressed(egui::Key::L) {
+                self.scroll(50.);
+            }
+            if i.key_pressed(egui::Key::H) {
+                self.scroll(-50.);
+            }
+            if i.key_pressed(egui::Key::K) {
+                self.change_zoom(1.1);
+            }
+            if i.key_pressed(egui::Key::J) {
+                self.change_zoom(0.9);
+            }
         }
-        if i.key_pressed(egui::Key::K) {
-            self.change_zoom(1.1);
+        if on_timeline
+            && i.modifiers.shift
+            && i.pointer.primary_pressed()
+            && let Some(pos) = i.pointer.interact_pos()
+            && self.rect.contains(pos)
+        {
+            self.range_drag = Some((self.time_at(pos.x), pos.x));
         }
-        if i.key_pressed(egui::Key::J) {
-            self.change_zoom(0.9);
+        if let Some((from, from_x)) = self.range_drag
+            && let Some(pos) = i.pointer.interact_pos()
+        {
+            if (pos.x - from_x).abs() >= MIN_RANGE_WIDTH {
+                let to = self.time_at(pos.x);
+                self.time_range = Some((from.min(to), from.max(to)));
+            }
+            if !i.pointer.primary_down() {
+                self.range_drag = None;
+            }
         }
-        if i.pointer.any_click() {
+        if on_timeline && i.pointer.any_click() && !i.modifiers.shift {
+            self.time_range = None;
             self.select(i.pointer.interact_pos().unwrap() - self.rect.min.to_vec2());
         }
         if i.smooth_scroll_delta != Vec2::ZERO {
@@ -478,15 +1313,52 @@ impl TemplateApp {
         }
     }
 
+    /// Draws the busy fraction of each column as the height of a bar, with
+    /// the busiest columns by count the most opaque.
+    fn draw_summary(&self, ui: &mut egui::Ui, view: &SummaryView) {
+        for (x, (syscalls, bios)) in view.columns.iter().enumerate() {
+            let bands = [
+                (syscalls, view.syscall_top, view.max_syscalls, egui::Color32::ORANGE),
+                (bios, view.bio_top, view.max_bios, egui::Color32::GREEN),
+            ];
+            for (bin, top, max_count, color) in bands {
+                if bin.count == 0 && bin.busy == 0 {
+                    continue;
+                }
+                let busy = (bin.busy as f32 * self.zoom).min(1.);
+                let height = (busy * view.height).max(1.);
+                let opacity = 0.3 + 0.7 * bin.count as f32 / max_count.max(1) as f32;
+                let bottom = self.rect.min.y + top + view.height;
+                let x = self.rect.min.x + x as f32;
+                ui.painter().rect_filled(
+                    Rect::from_min_max(Pos2::new(x, bottom - height), Pos2::new(x + 1., bottom)),
+                    0.,
+                    color.gamma_multiply(opacity),
+                );
+            }
+        }
+    }
+
     fn draw_objects(&self, ui: &mut egui::Ui) {
         for trace in self.traces.iter() {
-            for (i, syscall) in trace.on_screen_syscall.iter() {
-                let syscall_rect = &syscall.rect;
+            if let Some(view) = &trace.summary_view {
+                self.draw_summary(ui, view);
+            }
+            for (i, syscall_rect) in trace.on_screen_syscall.iter() {
+                let syscall = &trace.data.syscalls()[*i];
                 let syscall_rect = syscall_rect.translate(self.rect.min.to_vec2());
-                let color = match &syscall.syscall.kind {
-                    SyscallKind::Fsync => egui::Color32::ORANGE,
-                    SyscallKind::Write(_) => egui::Color32::RED,
+                if !self.rect.intersects(syscall_rect) {
+                    continue;
+                }
+                let mut color = match (self.color_by, FileKey::of(syscall)) {
+                    (ColorBy::Kind, _) => kind_color(&syscall.kind),
+                    (ColorBy::Process, _) => key_color(&syscall.comm),
+                    (ColorBy::File, Some(file)) => key_color(file),
+                    (ColorBy::File, None) => egui::Color32::GRAY,
                 };
+                if !trace.syscall_matches(*i) {
+                    color = color.gamma_multiply(UNMATCHED_OPACITY);
+                }
                 ui.painter().rect(syscall_rect, 0., color, Stroke::NONE);
 
                 if let Some(selected_syscall) = trace.selected_syscall
@@ -509,26 +1381,53 @@ impl TemplateApp {
                     );
                 }
             }
-            for (bio_index, on_screen_bio) in trace.on_screen_bio.iter() {
-                let bio_rect = &on_screen_bio.rect;
+            for (bio_index, bio_rect) in trace.on_screen_bio.iter() {
+                let bio = &trace.data.bios()[*bio_index];
                 let bio_rect = bio_rect.translate(self.rect.min.to_vec2());
-                ui.painter().rect(
-                    bio_rect,
-                    0.0,
-                    if on_screen_bio.bio.is_metadata {
-                        egui::Color32::BLUE
-                    } else {
-                        egui::Color32::GREEN
-                    },
-                    if let Some(selected_bio) = trace.selected_bio
-                        && selected_bio == *bio_index
-                    {
-                        Stroke::new(5., egui::Color32::RED)
-                    } else {
-                        Stroke::NONE
-                    },
-                );
-                if on_screen_bio.bio.is_flush {
+                if !self.rect.intersects(bio_rect) {
+                    continue;
+                }
+                let mut color = if self.color_by == ColorBy::Process {
+                    key_color(&bio.comm)
+                } else if bio.is_metadata {
+                    egui::Color32::BLUE
+                } else {
+                    egui::Color32::GREEN
+                };
+                if !trace.bio_matches(*bio_index) {
+                    color = color.gamma_multiply(UNMATCHED_OPACITY);
+                }
+                // Time spent queued is drawn faded, time on the device solid.
+                match bio.issue {
+                    Some(issue) => {
+                        let issue_x = bio_rect.min.x + (issue - bio.start) as f32 * self.zoom;
+                        let issue_x = issue_x.clamp(bio_rect.min.x, bio_rect.max.x);
+                        let (queued, on_device) = bio_rect.split_left_right_at_x(issue_x);
+                        ui.painter()
+                            .rect_filled(queued, 0.0, color.gamma_multiply(0.4));
+                        ui.painter().rect_filled(on_device, 0.0, color);
+                    }
+                    None => {
+                        ui.painter().rect_filled(bio_rect, 0.0, color);
+                    }
+                }
+                if let Some(merge) = bio.merge {
+                    let merge_x = bio_rect.min.x + (merge - bio.start) as f32 * self.zoom;
+                    ui.painter().line_segment(
+                        [
+                            Pos2::new(merge_x, bio_rect.min.y),
+                            Pos2::new(merge_x, bio_rect.max.y),
+                        ],
+                        Stroke::new(1.0, egui::Color32::WHITE),
+                    );
+                }
+                if let Some(selected_bio) = trace.selected_bio
+                    && selected_bio == *bio_index
+                {
+                    ui.painter()
+                        .rect_stroke(bio_rect, 0.0, Stroke::new(5., egui::Color32::RED));
+                }
+                if bio.is_flush {
                     // draw cross at rect.min
                     ui.painter().line_segment(
                         [bio_rect.min, bio_rect.min + Vec2::new(10., 10.)],
@@ -545,6 +1444,17 @@ impl TemplateApp {
             }
         }
 
+        if let Some((start, end)) = self.time_range {
+            let x = |time: i64| self.rect.min.x + (time - self.curr_time) as f32 * self.zoom;
+            let range =
+                Rect::from_x_y_ranges(x(start)..=x(end).max(x(start) + 1.), self.rect.y_range());
+            ui.painter().rect_filled(
+                range.intersect(self.rect),
+                0.,
+                egui::Color32::LIGHT_BLUE.gamma_multiply(0.2),
+            );
+        }
+
         let time_origin_x = self.rect.min.x - self.curr_time as f32 * self.zoom;
         let time_origin_visible =
             time_origin_x >= self.rect.min.x && time_origin_x <= self.rect.max.x;
@@ -559,41 +1469,125 @@ impl TemplateApp {
         }
     }
 
+    /// Draws ticks along the top of the timeline, labeled with the time
+    /// relative to the time origin and, below, with the absolute trace time
+    /// of the first trace.
+    fn draw_ruler(&self, ui: &mut egui::Ui, rect: Rect) {
+        let painter = ui.painter_at(rect);
+        let color = ui.visuals().text_color();
+        let font_id = FontId::monospace(12.);
+        painter.line_segment([rect.left_bottom(), rect.right_bottom()], Stroke::new(1., color));
+
+        // Aim for a tick every 100 pixels, at a round number of ns. Zoomed
+        // out far enough, that is more ns than an i64 holds.
+        let min_step = ((100. / self.zoom) as i64).max(1);
+        let magnitude = 10_i64.pow(min_step.ilog10());
+        let step = [1, 2, 5, 10]
+            .into_iter()
+            .map(|m| magnitude.saturating_mul(m))
+            .find(|&step| step >= min_step)
+            .unwrap();
+
+        let origin = self.traces.first().map(|trace| trace.time_origin);
+        let end = self
+            .curr_time
+            .saturating_add((rect.width() / self.zoom) as i64);
+        let mut time = self.curr_time.div_euclid(step) * step;
+        while time <= end {
+            let x = rect.min.x + (time - self.curr_time) as f32 * self.zoom;
+            painter.line_segment(
+                [Pos2::new(x, rect.max.y - 6.), Pos2::new(x, rect.max.y)],
+                Stroke::new(1., color),
+            );
+            painter.text(
+                Pos2::new(x + 2., rect.min.y),
+                Align2::LEFT_TOP,
+                tick_label(time, step),
+                font_id.clone(),
+                color,
+            );
+            if let Some(origin) = origin {
+                painter.text(
+                    Pos2::new(x + 2., rect.min.y + 14.),
+                    Align2::LEFT_TOP,
+                    abs_tick_label(origin.saturating_add(time), step),
+                    font_id.clone(),
+                    ui.visuals().weak_text_color(),
+                );
+            }
+            let Some(next) = time.checked_add(step) else {
+                break;
+            };
+            time = next;
+        }
+    }
+
     fn draw_y_axis(&self, ui: &mut egui::Ui, rect: Rect) {
         let font_id = FontId::monospace((100. / self.y_zoom).min(20.));
         let heading_font_id = FontId::monospace(20.);
         let mut last_rect = Rect::NOTHING;
         let mut last_offset = 0;
         for trace in self.traces.iter() {
-            if let Some(start_y) = trace
-                .on_screen_syscall
-                .first()
-                .map(|i| i.1.rect.max.y + self.rect.min.y)
-            {
-                let heading = trace.name.to_string();
+            let heading = trace.name.to_string();
+            ui.painter().text(
+                Pos2::new(rect.min.x, trace.top + self.rect.min.y),
+                Align2::LEFT_TOP,
+                heading,
+                heading_font_id.clone(),
+                ui.visuals().text_color(),
+            );
+
+            if let Some(view) = &trace.summary_view {
+                for (label, y) in [("syscalls", view.syscall_top), ("bios", view.bio_top)] {
+                    ui.painter().text(
+                        Pos2::new(rect.min.x, y + self.rect.min.y),
+                        Align2::LEFT_TOP,
+                        format!("{} (summary)", label),
+                        FontId::monospace(14.),
+                        ui.visuals().text_color(),
+                    );
+                }
+            }
+
+            for (tid, comm, y) in trace.thread_lanes.iter() {
+                let mut label = format!("{} {}", comm, tid);
+                if trace.collapsed_threads.contains(tid) {
+                    label += " (collapsed)";
+                }
                 ui.painter().text(
-                    Pos2::new(rect.min.x, start_y),
+                    Pos2::new(rect.min.x, y + self.rect.min.y),
                     Align2::LEFT_TOP,
-                    heading,
-                    heading_font_id.clone(),
+                    label,
+                    FontId::monospace(14.),
                     ui.visuals().text_color(),
                 );
-                let _syscall_rect = ui.painter().text(
-                    Pos2::new(rect.width() * 0.7, start_y),
-                    Align2::RIGHT_TOP,
-                    "Syscalls",
-                    heading_font_id.clone(),
+            }
+
+            for (dev, y) in trace.dev_lanes.iter() {
+                let mut heading = dev.to_string();
+                if trace.collapsed_devs.contains(dev) {
+                    heading += " (collapsed)";
+                }
+                ui.painter().text(
+                    Pos2::new(rect.min.x, y + self.rect.min.y),
+                    Align2::LEFT_TOP,
+                    heading,
+                    FontId::monospace(14.),
                     ui.visuals().text_color(),
                 );
             }
 
             let mut painted = HashSet::new();
-            for (bio_index, on_screen_bio) in trace.on_screen_bio.iter() {
+            for (bio_index, bio_rect) in trace.on_screen_bio.iter() {
+                let bio = &trace.data.bios()[*bio_index];
+                if trace.collapsed_devs.contains(&bio.dev) {
+                    continue;
+                }
                 // print offset at y
-                let bio_rect = on_screen_bio.rect.translate(self.rect.min.to_vec2());
+                let bio_rect = bio_rect.translate(self.rect.min.to_vec2());
                 let y = bio_rect.min.y;
-                let offset = on_screen_bio.bio.offset;
-                if !painted.insert(offset) || offset == 0 {
+                let offset = bio.offset;
+                if !painted.insert((&bio.dev, offset)) || offset == 0 {
                     continue;
                 }
                 let text = format!("0x{:x}", offset);
@@ -625,6 +1619,12 @@ impl eframe::App for TemplateApp {
                 let is_web = cfg!(target_arch = "wasm32");
                 if !is_web {
                     ui.menu_button("File", |ui| {
+                        if ui.button("Open…").clicked() {
+                            ui.close_menu();
+                            if let Some(dir) = rfd::FileDialog::new().pick_folder() {
+                                self.open(&dir);
+                            }
+                        }
                         if ui.button("Quit").clicked() {
                             ctx.send_viewport_cmd(egui::ViewportCommand::Close);
                         }
@@ -636,6 +1636,36 @@ impl eframe::App for TemplateApp {
             });
         });
 
+        egui::TopBottomPanel::top("filter_bar").show(ctx, |ui| self.filter_bar(ui));
+
+        self.flame_window(ctx);
+
+        let dropped: Vec<PathBuf> = ctx.input(|i| {
+            i.raw
+                .dropped_files
+                .iter()
+                .filter_map(|file| file.path.clone())
+                .collect()
+        });
+        for path in dropped {
+            self.open(&path);
+        }
+
+        if !self.errors.is_empty() {
+            egui::Window::new("Error")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
+                .show(ctx, |ui| {
+                    for error in &self.errors {
+                        ui.label(error);
+                    }
+                    if ui.button("OK").clicked() {
+                        self.errors.clear();
+                    }
+                });
+        }
+
         egui::SidePanel::right("info").show(ctx, |ui| {
             // The side panel is often a good place for tools and options.
 
@@ -646,8 +1676,47 @@ impl eframe::App for TemplateApp {
                 ui.add(egui::Slider::new(&mut self.zoom, 0.000001..=0.01).text("zoom"));
             });
 
+            ui.horizontal(|ui| {
+                ui.label("Color by:");
+                ui.radio_value(&mut self.color_by, ColorBy::Kind, "kind");
+                ui.radio_value(&mut self.color_by, ColorBy::File, "file");
+                ui.radio_value(&mut self.color_by, ColorBy::Process, "process");
+            });
+
+            ui.checkbox(&mut self.show_flame, "Flame graph");
+
+            let mut filters_changed = false;
+            for t in &mut self.traces {
+                filters_changed |= t.file_filter_panel(ui);
+                filters_changed |= t.process_panel(ui);
+                filters_changed |= t.thread_panel(ui);
+                filters_changed |= t.lane_panel(ui);
+            }
+            if filters_changed {
+                for t in &mut self.traces {
+                    t.summaries = None;
+                }
+                self.layout();
+            }
+
             ui.separator();
 
+            ui.horizontal(|ui| {
+                ui.label("Latency of:");
+                ui.radio_value(&mut self.latency_of_viewport, false, "whole trace");
+                ui.radio_value(&mut self.latency_of_viewport, true, "viewport");
+            });
+            let viewport = self.latency_of_viewport.then(|| {
+                (self.curr_time, self.curr_time + (self.rect.width() / self.zoom) as i64)
+            });
+            let mut jump = None;
+            for t in &mut self.traces {
+                jump = jump.or(t.latency_panel(ui, viewport));
+            }
+            if let Some(time) = jump {
+                self.scroll_to(time);
+            }
+
             ui.separator();
 
             for t in &mut self.traces {
@@ -683,14 +1752,219 @@ impl eframe::App for TemplateApp {
         });
 
         egui::CentralPanel::default().show(ctx, |ui| {
-            let (_id, rect) = ui.allocate_space(ui.available_size());
+            let (_id, ruler) = ui.allocate_space(Vec2::new(ui.available_width(), 34.));
+            self.draw_ruler(ui, ruler);
+            let (id, rect) = ui.allocate_space(ui.available_size());
             self.set_rect(rect);
-            ui.input(|i| self.input(i));
+            let keys = !ctx.wants_keyboard_input();
+            let on_timeline = ui.rect_contains_pointer(rect);
+            ui.input(|i| self.input(i, keys, on_timeline));
             self.draw_objects(ui);
+
+            let response = ui.interact(rect, id, egui::Sense::hover());
+            let hit = response
+                .hover_pos()
+                .and_then(|pos| self.hit_test(pos - rect.min.to_vec2()));
+            if let Some((t, event)) = hit {
+                response.on_hover_ui_at_pointer(|ui| self.tooltip(ui, t, event));
+            } else if let Some(pos) = response.hover_pos()
+                && let Some((events, bin)) = self.summary_at(pos - rect.min.to_vec2())
+            {
+                response.on_hover_ui_at_pointer(|ui| {
+                    ui.label(format!(
+                        "{} {}\n{} bytes\nbusy: {:.0}%",
+                        bin.count,
+                        events,
+                        bin.bytes,
+                        (bin.busy as f32 * self.zoom * 100.).min(100.)
+                    ));
+                });
+            }
         });
     }
 }
 
+/// Draws `root` as an icicle graph, callers above callees, each frame as
+/// wide as its weight. Returns the call path of the frame clicked below the
+/// root, outermost first, if any.
+fn draw_flame_graph(ui: &mut egui::Ui, root: &Frame, weight: Weight) -> Option<Vec<String>> {
+    let size = Vec2::new(ui.available_width(), root.depth() as f32 * FLAME_ROW_HEIGHT);
+    let (rect, response) = ui.allocate_exact_size(size, egui::Sense::click());
+    let painter = ui.painter_at(rect);
+    let mut hovered = None;
+    draw_flame_frame(
+        &painter,
+        root,
+        &mut Vec::new(),
+        rect.min,
+        rect.width(),
+        response.hover_pos(),
+        &mut hovered,
+    );
+    let (path, frame_weight) = hovered?;
+    let function = path.last().copied().unwrap_or(&root.function);
+    let share = frame_weight as f64 / root.weight as f64 * 100.;
+    let amount = match weight {
+        Weight::Count => format!("{} bios", frame_weight),
+        Weight::Sectors => format!("{} sectors", frame_weight),
+        Weight::Latency => format_ns(frame_weight as i64),
+    };
+    let clicked = response.clicked();
+    response.on_hover_text(format!("{}\n{} ({:.1}%)", function, amount, share));
+    // The root stands for every bio, so it does not filter anything.
+    (clicked && !path.is_empty()).then(|| path.into_iter().map(str::to_owned).collect())
+}
+
+/// Draws `frame`, reached from the root through the functions in `path`, at
+/// `min` and its callees below it, and finds the path and weight of the
+/// frame under `hover`.
+fn draw_flame_frame<'a>(
+    painter: &egui::Painter,
+    frame: &'a Frame,
+    path: &mut Vec<&'a str>,
+    min: Pos2,
+    width: f32,
+    hover: Option<Pos2>,
+    hovered: &mut Option<(Vec<&'a str>, u64)>,
+) {
+    let rect = Rect::from_min_size(min, Vec2::new(width, FLAME_ROW_HEIGHT - 1.));
+    painter.rect_filled(rect, 2., key_color(&frame.function));
+    if width > 30. {
+        painter
+            .with_clip_rect(rect.intersect(painter.clip_rect()))
+            .text(
+                rect.left_center() + Vec2::new(3., 0.),
+                Align2::LEFT_CENTER,
+                &frame.function,
+                FontId::monospace(12.),
+                egui::Color32::BLACK,
+            );
+    }
+    if hover.is_some_and(|pos| rect.contains(pos)) {
+        *hovered = Some((path.clone(), frame.weight));
+    }
+    let mut x = min.x;
+    for child in frame.children.values() {
+        let child_width = child.weight as f32 / frame.weight as f32 * width;
+        if child_width >= 1. {
+            let child_min = Pos2::new(x, min.y + FLAME_ROW_HEIGHT);
+            path.push(&child.function);
+            draw_flame_frame(painter, child, path, child_min, child_width, hover, hovered);
+            path.pop();
+        }
+        x += child_width;
+    }
+}
+
+/// Draws the counts of `buckets` of `histogram` as bars, the `selected`
+/// bucket highlighted. Returns the bucket clicked, if it has any events.
+fn draw_histogram(
+    ui: &mut egui::Ui,
+    histogram: &Histogram,
+    buckets: std::ops::Range<usize>,
+    selected: Option<usize>,
+) -> Option<usize> {
+    let size = Vec2::new(HISTOGRAM_WIDTH, ui.spacing().interact_size.y);
+    let (rect, response) = ui.allocate_exact_size(size, egui::Sense::click());
+    if buckets.is_empty() {
+        return None;
+    }
+    let bar_width = rect.width() / buckets.len() as f32;
+    let max = histogram.counts.iter().copied().max().unwrap_or(0).max(1);
+    for (i, bucket) in buckets.clone().enumerate() {
+        let count = histogram.counts.get(bucket).copied().unwrap_or(0);
+        if count == 0 {
+            continue;
+        }
+        let height = (count as f32 / max as f32 * rect.height()).max(1.);
+        let x = rect.min.x + i as f32 * bar_width;
+        let bar = Rect::from_min_max(
+            Pos2::new(x, rect.max.y - height),
+            Pos2::new(x + (bar_width - 1.).max(1.), rect.max.y),
+        );
+        let color = if selected == Some(bucket) {
+            egui::Color32::RED
+        } else {
+            ui.visuals().text_color()
+        };
+        ui.painter().rect_filled(bar, 0., color);
+    }
+    let pos = response.hover_pos()?;
+    let bucket = (buckets.start + ((pos.x - rect.min.x) / bar_width) as usize).min(buckets.end - 1);
+    let count = histogram.counts.get(bucket).copied().unwrap_or(0);
+    let (low, high) = Histogram::bounds(bucket);
+    let clicked = response.clicked();
+    response.on_hover_text(format!(
+        "{} to {}: {}",
+        format_ns(low),
+        format_ns(high),
+        count
+    ));
+    (clicked && count > 0).then_some(bucket)
+}
+
+/// Formats a duration in the unit that suits it.
+fn format_ns(ns: i64) -> String {
+    match ns.abs() {
+        ..1_000 => format!("{} ns", ns),
+        1_000..1_000_000 => format!("{:.2} µs", ns as f64 / 1e3),
+        1_000_000..1_000_000_000 => format!("{:.2} ms", ns as f64 / 1e6),
+        _ => format!("{:.3} s", ns as f64 / 1e9),
+    }
+}
+
+/// Labels a tick at `ns`, a multiple of `step`, in the largest unit that
+/// `step` is a whole number of.
+fn tick_label(ns: i64, step: i64) -> String {
+    let (scale, unit) = match step {
+        ..1_000 => (1, "ns"),
+        1_000..1_000_000 => (1_000, "µs"),
+        1_000_000..1_000_000_000 => (1_000_000, "ms"),
+        _ => (1_000_000_000, "s"),
+    };
+    format!("{} {}", ns / scale, unit)
+}
+
+/// The least of `keys` after `from` going `forward`, or else the greatest
+/// before it.
+fn next_match<K: Ord>(keys: impl Iterator<Item = K>, from: K, forward: bool) -> Option<K> {
+    if forward {
+        keys.filter(|key| *key > from).min()
+    } else {
+        keys.filter(|key| *key < from).max()
+    }
+}
+
+/// Labels a tick at the absolute time `ns` in seconds, down to `step`.
+fn abs_tick_label(ns: i64, step: i64) -> String {
+    let decimals = 9_usize.saturating_sub(step.ilog10() as usize);
+    format!("{:.*} s", decimals, ns as f64 / 1e9)
+}
+
+fn kind_color(kind: &SyscallKind) -> egui::Color32 {
+    match kind {
+        SyscallKind::Fsync => egui::Color32::ORANGE,
+        SyscallKind::Fdatasync => egui::Color32::from_rgb(255, 200, 0),
+        SyscallKind::SyncFileRange(_) => egui::Color32::from_rgb(200, 100, 0),
+        SyscallKind::Msync => egui::Color32::BROWN,
+        SyscallKind::Syncfs => egui::Color32::from_rgb(255, 120, 160),
+        SyscallKind::Sync => egui::Color32::DARK_RED,
+        SyscallKind::Write(_) => egui::Color32::RED,
+        SyscallKind::Read(_) => egui::Color32::LIGHT_BLUE,
+        SyscallKind::Pread(_) => egui::Color32::from_rgb(0, 160, 160),
+        SyscallKind::Preadv(_) => egui::Color32::from_rgb(140, 90, 220),
+        SyscallKind::Readahead(_) => egui::Color32::YELLOW,
+    }
+}
+
+/// A color that stays the same for `key` across frames and traces.
+fn key_color(key: impl Hash) -> egui::Color32 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    let hue = (hasher.finish() % 360) as f32 / 360.;
+    egui::ecolor::Hsva::new(hue, 0.7, 0.9, 1.0).into()
+}
+
 fn powered_by_egui_and_eframe(ui: &mut egui::Ui) {
     ui.horizontal(|ui| {
         ui.spacing_mut().item_spacing.x = 0.0;
@@ -704,3 +1978,31 @@ fn powered_by_egui_and_eframe(ui: &mut egui::Ui) {
         ui.label(".");
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{next_match, EventIndex};
+
+    #[test]
+    fn steps_through_matches_that_start_together() {
+        let matches = [
+            (10, 0, EventIndex::Syscall(0)),
+            (10, 0, EventIndex::Bio(3)),
+            (10, 1, EventIndex::Bio(0)),
+            (20, 0, EventIndex::Bio(1)),
+            (5, 0, EventIndex::Bio(2)),
+        ];
+        let mut at = (5, 0, EventIndex::Bio(2));
+        let mut visited = Vec::new();
+        while let Some(next) = next_match(matches.into_iter(), at, true) {
+            visited.push(next);
+            at = next;
+        }
+        assert_eq!(visited, [matches[1], matches[0], matches[2], matches[3]]);
+        assert_eq!(next_match(matches.into_iter(), at, false), Some(matches[2]));
+        assert_eq!(
+            next_match(matches.into_iter(), matches[1], false),
+            Some(matches[4])
+        );
+    }
+}
@@ -0,0 +1,245 @@
+//! A loaded trace, indexed for queries by time.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::error::Result;
+use crate::interval::IntervalIndex;
+use crate::load::{read_json, read_stack_traces, StackTrace};
+use crate::stats::{self, LatencyGroup};
+use crate::trace::{Bio, Syscall, SyscallStats};
+
+/// The bios, syscalls and stack traces of a trace, as written by
+/// `trace-process`.
+pub struct TraceData {
+    bios: Vec<Bio>,
+    syscalls: Vec<Syscall>,
+    stack_traces: Vec<StackTrace>,
+    /// Indices into `bios` by the time span of the bio.
+    bio_intervals: IntervalIndex<usize>,
+    /// Indices into `syscalls` by the time span of the syscall.
+    syscall_intervals: IntervalIndex<usize>,
+}
+
+/// The events in a time range.
+pub struct RangeStats {
+    /// Latency of the completed bios by `read` or `write`, `flush` and
+    /// `metadata`.
+    pub bios: BTreeMap<&'static str, LatencyGroup>,
+    /// Latency of the finished syscalls by the name of their kind.
+    pub syscalls: BTreeMap<&'static str, LatencyGroup>,
+    pub flushes: u64,
+    pub write_sectors: u64,
+    pub read_sectors: u64,
+}
+
+impl TraceData {
+    pub fn new(bios: Vec<Bio>, syscalls: Vec<Syscall>, stack_traces: Vec<StackTrace>) -> Self {
+        let bio_intervals = IntervalIndex::new(
+            bios.iter()
+                .enumerate()
+                .map(|(i, bio)| (bio.start, bio.end.unwrap_or(bio.start), i)),
+        );
+        let syscall_intervals = IntervalIndex::new(
+            syscalls
+                .iter()
+                .enumerate()
+                .map(|(i, syscall)| (syscall.start, syscall.end.unwrap_or(syscall.start), i)),
+        );
+        Self {
+            bios,
+            syscalls,
+            stack_traces,
+            bio_intervals,
+            syscall_intervals,
+        }
+    }
+
+    /// Loads the trace in `dir`: `bio.json`, `stack.csv` and `syscall.json`.
+    pub fn load(dir: &Path) -> Result<Self> {
+        Self::load_files(
+            &dir.join("bio.json"),
+            &dir.join("stack.csv"),
+            &dir.join("syscall.json"),
+        )
+    }
+
+    pub fn load_files(
+        bio_json: &Path,
+        stack_trace_csv: &Path,
+        syscall_json: &Path,
+    ) -> Result<Self> {
+        let bios = read_json(bio_json)?;
+        let stack_traces = read_stack_traces(stack_trace_csv)?;
+        let syscalls = read_json(syscall_json)?;
+        Ok(Self::new(bios, syscalls, stack_traces))
+    }
+
+    pub fn bios(&self) -> &[Bio] {
+        &self.bios
+    }
+
+    pub fn syscalls(&self) -> &[Syscall] {
+        &self.syscalls
+    }
+
+    pub fn stack_traces(&self) -> &[StackTrace] {
+        &self.stack_traces
+    }
+
+    /// The frames of the stack trace that queued `bio`, innermost first, or
+    /// none if it is missing from `stack.csv`.
+    pub fn stack_trace(&self, bio: &Bio) -> &[(String, String)] {
+        self.stack_traces
+            .get(bio.stack_trace)
+            .map_or(&[], |frames| frames)
+    }
+
+    /// The start of the first syscall, or of the first bio if there are no
+    /// syscalls.
+    pub fn time_origin(&self) -> i64 {
+        self.syscalls
+            .iter()
+            .map(|syscall| syscall.start)
+            .min()
+            .or(self.bios.iter().map(|bio| bio.start).min())
+            .unwrap_or(0)
+    }
+
+    /// Indices of the bios that overlap `start..=end`, ordered by start.
+    pub fn bios_in(&self, start: i64, end: i64) -> impl Iterator<Item = usize> + '_ {
+        self.bio_intervals.overlapping(start, end).copied()
+    }
+
+    /// Indices of the syscalls that overlap `start..=end`, ordered by start.
+    pub fn syscalls_in(&self, start: i64, end: i64) -> impl Iterator<Item = usize> + '_ {
+        self.syscall_intervals.overlapping(start, end).copied()
+    }
+
+    /// Attributes to the syscall at `idx` the bios that start and end within
+    /// it, keeping the result in its `stats`.
+    pub fn syscall_stats(&mut self, idx: usize) -> &SyscallStats {
+        let syscall = &self.syscalls[idx];
+        if syscall.stats.is_none() {
+            let stats = stats::syscall_stats(&self.bio_intervals, &self.bios, syscall);
+            self.syscalls[idx].stats = Some(stats);
+        }
+        self.syscalls[idx].stats.as_ref().unwrap()
+    }
+
+    /// Summarizes the events that overlap `range`, or all events.
+    pub fn range_stats(&self, range: Option<(i64, i64)>) -> RangeStats {
+        let (bios, syscalls): (Vec<usize>, Vec<usize>) = match range {
+            Some((start, end)) => (
+                self.bios_in(start, end).collect(),
+                self.syscalls_in(start, end).collect(),
+            ),
+            None => (
+                (0..self.bios.len()).collect(),
+                (0..self.syscalls.len()).collect(),
+            ),
+        };
+        let mut stats = RangeStats {
+            bios: stats::bio_latency_groups(bios.iter().map(|&idx| (idx, &self.bios[idx]))),
+            syscalls: stats::syscall_latency_groups(
+                syscalls.iter().map(|&idx| (idx, &self.syscalls[idx])),
+            ),
+            flushes: 0,
+            write_sectors: 0,
+            read_sectors: 0,
+        };
+        for bio in bios.iter().map(|&idx| &self.bios[idx]) {
+            if bio.is_flush {
+                stats.flushes += 1;
+            }
+            if bio.is_write {
+                stats.write_sectors += bio.size;
+            } else {
+                stats.read_sectors += bio.size;
+            }
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TraceData;
+    use crate::trace::{Bio, Syscall, SyscallKind};
+
+    fn bio(start: i64, end: Option<i64>, size: u64, is_write: bool, is_flush: bool) -> Bio {
+        Bio {
+            size,
+            is_flush,
+            is_write,
+            start,
+            end,
+            ..Bio::default()
+        }
+    }
+
+    fn fsync(start: i64, end: i64) -> Syscall {
+        Syscall {
+            kind: SyscallKind::Fsync,
+            start,
+            end: Some(end),
+            ..Syscall::default()
+        }
+    }
+
+    fn trace() -> TraceData {
+        TraceData::new(
+            vec![
+                bio(100, Some(200), 8, true, false),
+                bio(150, Some(400), 16, true, true),
+                bio(500, Some(600), 4, false, false),
+                bio(0, Some(10_000), 2, false, false),
+            ],
+            vec![fsync(50, 450), fsync(1000, 2000)],
+            vec![vec![("submit_bio".to_owned(), "blk-core.c:1".to_owned())]],
+        )
+    }
+
+    #[test]
+    fn time_range_queries() {
+        let trace = trace();
+        assert_eq!(trace.bios_in(160, 170).collect::<Vec<_>>(), [3, 0, 1]);
+        assert_eq!(trace.bios_in(450, 460).collect::<Vec<_>>(), [3]);
+        assert_eq!(trace.syscalls_in(450, 1000).collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(trace.time_origin(), 50);
+        assert_eq!(trace.stack_trace(&trace.bios()[0])[0].0, "submit_bio");
+    }
+
+    #[test]
+    fn syscall_stats_count_the_bios_within() {
+        let mut trace = trace();
+        let stats = trace.syscall_stats(0).clone();
+        assert_eq!(stats.write_sectors, 24);
+        assert_eq!(stats.read_sectors, 0);
+        assert_eq!(stats.flushes, 1);
+        // Bios are in flight from 100 to 400 of 50 to 450.
+        assert!((stats.frac_io_time - 0.75).abs() < 1e-9);
+        assert!(trace.syscalls()[0].stats.is_some());
+
+        let stats = trace.syscall_stats(1);
+        assert_eq!(stats.write_sectors + stats.read_sectors, 0);
+        assert_eq!(stats.frac_io_time, 0.);
+    }
+
+    #[test]
+    fn range_stats() {
+        let trace = trace();
+        let stats = trace.range_stats(Some((460, 550)));
+        assert_eq!(stats.read_sectors, 6);
+        assert_eq!(stats.write_sectors, 0);
+        assert_eq!(stats.bios["read"].summary.count, 2);
+        assert!(!stats.bios.contains_key("write"));
+        assert!(stats.syscalls.is_empty());
+
+        let stats = trace.range_stats(None);
+        assert_eq!(stats.flushes, 1);
+        assert_eq!(stats.bios["write"].summary.count, 2);
+        assert_eq!(stats.bios["flush"].summary.max, 250);
+        assert_eq!(stats.syscalls["fsync"].summary.count, 2);
+    }
+}
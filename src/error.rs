@@ -0,0 +1,98 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+/// Everything that can go wrong while processing or loading a trace.
+#[derive(Debug)]
+pub enum TraceError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Csv {
+        path: PathBuf,
+        source: csv::Error,
+    },
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    /// A record that does not have the fields its event type needs.
+    Malformed {
+        path: PathBuf,
+        line: u64,
+        message: String,
+    },
+    /// Addresses in `object` could not be turned into source locations.
+    Symbolize { object: PathBuf, message: String },
+}
+
+pub type Result<T> = std::result::Result<T, TraceError>;
+
+impl TraceError {
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        Self::Io {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub fn csv(path: &Path, source: csv::Error) -> Self {
+        Self::Csv {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub fn json(path: &Path, source: serde_json::Error) -> Self {
+        Self::Json {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub fn malformed(path: &Path, line: u64, message: impl Into<String>) -> Self {
+        Self::Malformed {
+            path: path.to_owned(),
+            line,
+            message: message.into(),
+        }
+    }
+
+    pub fn symbolize(object: &Path, message: impl Into<String>) -> Self {
+        Self::Symbolize {
+            object: object.to_owned(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            TraceError::Csv { path, source } => write!(f, "{}: {}", path.display(), source),
+            TraceError::Json { path, source } => write!(f, "{}: {}", path.display(), source),
+            TraceError::Malformed {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            TraceError::Symbolize { object, message } => {
+                write!(f, "cannot symbolize {}: {}", object.display(), message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TraceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TraceError::Io { source, .. } => Some(source),
+            TraceError::Csv { source, .. } => Some(source),
+            TraceError::Json { source, .. } => Some(source),
+            TraceError::Malformed { .. } | TraceError::Symbolize { .. } => None,
+        }
+    }
+}
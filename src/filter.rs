@@ -0,0 +1,770 @@
+//! Filter expressions for picking out bios and syscalls, such as
+//! `bio.is_flush && size > 8`, `syscall.kind == fsync && latency > 5ms` or
+//! `stack contains btrfs_sync_log`.
+//!
+//! An expression is comparisons and fields joined with `&&`, `||` and `!`,
+//! grouped with parentheses. A field prefixed with `bio.` or `syscall.` only
+//! matches that kind of event, and `bio` and `syscall` alone match every
+//! event of their kind. A comparison on a field an event does not have is
+//! false.
+//!
+//! `stack contains f` matches bios with a function containing `f` on their
+//! stack, and `stack under "a;b;c"` those whose stack starts, outermost
+//! frame first, with exactly the functions `a`, `b` and `c`.
+
+use std::fmt;
+
+use crate::trace::{Bio, Syscall, SyscallKind};
+
+/// An event to match a filter against.
+#[derive(Clone, Copy)]
+pub enum Event<'a> {
+    /// A bio with the frames of its stack trace, as function and location.
+    Bio(&'a Bio, &'a [(String, String)]),
+    Syscall(&'a Syscall),
+}
+
+/// Where a filter expression could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Byte offset into the expression.
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at {}: {}", self.position, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    Any,
+    Bio,
+    Syscall,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    /// Matches every event in its scope.
+    Event,
+    Kind,
+    Latency,
+    Start,
+    End,
+    /// In sectors.
+    Size,
+    Offset,
+    Bytes,
+    IsFlush,
+    IsWrite,
+    IsMetadata,
+    Pid,
+    Tid,
+    Comm,
+    Cpu,
+    Dev,
+    File,
+    Fd,
+    Stack,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "kind" => Field::Kind,
+            "latency" => Field::Latency,
+            "start" => Field::Start,
+            "end" => Field::End,
+            "size" => Field::Size,
+            "offset" => Field::Offset,
+            "bytes" => Field::Bytes,
+            "is_flush" => Field::IsFlush,
+            "is_write" => Field::IsWrite,
+            "is_metadata" => Field::IsMetadata,
+            "pid" => Field::Pid,
+            "tid" => Field::Tid,
+            "comm" => Field::Comm,
+            "cpu" => Field::Cpu,
+            "dev" => Field::Dev,
+            "file" => Field::File,
+            "fd" => Field::Fd,
+            "stack" => Field::Stack,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+    /// The stack starts with a `;`-separated call path, outermost first.
+    Under,
+}
+
+impl Op {
+    fn symbol(self) -> &'static str {
+        match self {
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Contains => "contains",
+            Op::Under => "under",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Number(i64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    /// A field on its own, true if it is a true flag or a present field.
+    Test(Scope, Field),
+    Compare(Scope, Field, Op, Literal),
+}
+
+/// The value of a field of one event.
+enum Value<'a> {
+    Number(i64),
+    Flag(bool),
+    Text(String),
+    Frames(&'a [(String, String)]),
+}
+
+/// A parsed filter expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            pos: 0,
+            len: text.len(),
+        };
+        let expr = parser.or()?;
+        if let Some((position, token)) = parser.tokens.get(parser.pos) {
+            return Err(ParseError {
+                position: *position,
+                message: format!("unexpected {}", token),
+            });
+        }
+        Ok(Self { expr })
+    }
+
+    pub fn matches(&self, event: Event) -> bool {
+        eval(&self.expr, event)
+    }
+}
+
+fn eval(expr: &Expr, event: Event) -> bool {
+    match expr {
+        Expr::Or(a, b) => eval(a, event) || eval(b, event),
+        Expr::And(a, b) => eval(a, event) && eval(b, event),
+        Expr::Not(a) => !eval(a, event),
+        Expr::Test(scope, field) => match field_value(*scope, *field, event) {
+            Some(Value::Flag(flag)) => flag,
+            Some(_) => true,
+            None => false,
+        },
+        Expr::Compare(scope, field, op, literal) => match field_value(*scope, *field, event) {
+            Some(value) => compare(&value, *op, literal),
+            None => false,
+        },
+    }
+}
+
+fn compare(value: &Value, op: Op, literal: &Literal) -> bool {
+    match (value, literal) {
+        (Value::Number(value), Literal::Number(literal)) => match op {
+            Op::Eq => value == literal,
+            Op::Ne => value != literal,
+            Op::Lt => value < literal,
+            Op::Le => value <= literal,
+            Op::Gt => value > literal,
+            Op::Ge => value >= literal,
+            Op::Contains | Op::Under => false,
+        },
+        (Value::Flag(value), Literal::Text(literal)) => {
+            let literal = match literal.as_str() {
+                "true" => true,
+                "false" => false,
+                _ => return false,
+            };
+            match op {
+                Op::Eq => *value == literal,
+                Op::Ne => *value != literal,
+                _ => false,
+            }
+        }
+        (Value::Text(value), Literal::Text(literal)) => match op {
+            Op::Eq => value == literal,
+            Op::Ne => value != literal,
+            Op::Contains => value.contains(literal.as_str()),
+            _ => false,
+        },
+        (Value::Frames(frames), Literal::Text(literal)) => match op {
+            Op::Contains => frames
+                .iter()
+                .any(|(function, _)| function.contains(literal.as_str())),
+            Op::Under => {
+                let path: Vec<&str> = literal.split(';').collect();
+                frames.len() >= path.len()
+                    && frames
+                        .iter()
+                        .rev()
+                        .zip(path)
+                        .all(|((function, _), caller)| function == caller)
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn field_value<'a>(scope: Scope, field: Field, event: Event<'a>) -> Option<Value<'a>> {
+    let number = |n: u64| Some(Value::Number(n as i64));
+    match event {
+        Event::Bio(bio, frames) => {
+            if scope == Scope::Syscall {
+                return None;
+            }
+            match field {
+                Field::Event => Some(Value::Flag(true)),
+                Field::Kind => Some(Value::Text(
+                    if bio.is_write { "write" } else { "read" }.to_owned(),
+                )),
+                Field::Latency => Some(Value::Number(bio.end? - bio.start)),
+                Field::Start => Some(Value::Number(bio.start)),
+                Field::End => Some(Value::Number(bio.end?)),
+                Field::Size => number(bio.size),
+                Field::Offset => number(bio.offset),
+                Field::Bytes => number(bio.size * 512),
+                Field::IsFlush => Some(Value::Flag(bio.is_flush)),
+                Field::IsWrite => Some(Value::Flag(bio.is_write)),
+                Field::IsMetadata => Some(Value::Flag(bio.is_metadata)),
+                Field::Pid => number(bio.pid),
+                Field::Tid => number(bio.tid),
+                Field::Comm => Some(Value::Text(bio.comm.clone())),
+                Field::Cpu => number(bio.cpu.into()),
+                Field::Dev => Some(Value::Text(bio.dev.to_string())),
+                Field::Stack => Some(Value::Frames(frames)),
+                Field::File | Field::Fd => None,
+            }
+        }
+        Event::Syscall(syscall) => {
+            if scope == Scope::Bio {
+                return None;
+            }
+            match field {
+                Field::Event => Some(Value::Flag(true)),
+                Field::Kind => Some(Value::Text(syscall.kind.name().to_owned())),
+                Field::Latency => Some(Value::Number(syscall.end? - syscall.start)),
+                Field::Start => Some(Value::Number(syscall.start)),
+                Field::End => Some(Value::Number(syscall.end?)),
+                Field::Bytes => number(syscall.kind.bytes()?),
+                Field::IsWrite => Some(Value::Flag(matches!(syscall.kind, SyscallKind::Write(_)))),
+                Field::Pid => number(syscall.pid),
+                Field::Tid => number(syscall.tid),
+                Field::Comm => Some(Value::Text(syscall.comm.clone())),
+                Field::Cpu => number(syscall.cpu.into()),
+                Field::File => Some(Value::Text(syscall.file.as_ref()?.to_string())),
+                Field::Fd => Some(Value::Number(syscall.file.as_ref()?.fd.into())),
+                Field::Size
+                | Field::Offset
+                | Field::IsFlush
+                | Field::IsMetadata
+                | Field::Dev
+                | Field::Stack => None,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// A field, keyword or bare word, e.g. `bio.is_flush` or `fsync`.
+    Word(String),
+    Number(i64),
+    /// A quoted string.
+    Text(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "`{}`", word),
+            Token::Number(n) => write!(f, "`{}`", n),
+            Token::Text(text) => write!(f, "{:?}", text),
+            Token::Op(op) => write!(f, "`{}`", op.symbol()),
+            Token::And => write!(f, "`&&`"),
+            Token::Or => write!(f, "`||`"),
+            Token::Not => write!(f, "`!`"),
+            Token::Open => write!(f, "`(`"),
+            Token::Close => write!(f, "`)`"),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let error = |position, message: &str| ParseError {
+        position,
+        message: message.to_owned(),
+    };
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let rest = &text[start..];
+        let symbol = [
+            ("&&", Token::And),
+            ("||", Token::Or),
+            ("==", Token::Op(Op::Eq)),
+            ("!=", Token::Op(Op::Ne)),
+            ("<=", Token::Op(Op::Le)),
+            (">=", Token::Op(Op::Ge)),
+            ("<", Token::Op(Op::Lt)),
+            (">", Token::Op(Op::Gt)),
+            ("!", Token::Not),
+            ("(", Token::Open),
+            (")", Token::Close),
+        ]
+        .into_iter()
+        .find(|(symbol, _)| rest.starts_with(symbol));
+        if let Some((symbol, token)) = symbol {
+            for _ in 0..symbol.len() {
+                chars.next();
+            }
+            tokens.push((start, token));
+        } else if c == '"' {
+            chars.next();
+            let mut string = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, c)) => string.push(c),
+                        None => return Err(error(start, "unterminated string")),
+                    },
+                    Some((_, c)) => string.push(c),
+                    None => return Err(error(start, "unterminated string")),
+                }
+            }
+            tokens.push((start, Token::Text(string)));
+        } else if c.is_alphanumeric()
+            || c == '_'
+            || c == '.'
+            || (c == '-' && rest[1..].starts_with(|c: char| c.is_ascii_digit()))
+        {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_' || c == '.' || c == '/' || c == '-') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let word = &text[start..end];
+            let token = if word == "contains" {
+                Token::Op(Op::Contains)
+            } else if word == "under" {
+                Token::Op(Op::Under)
+            } else if c.is_ascii_digit() || c == '-' {
+                Token::Number(parse_number(word).ok_or_else(|| error(start, "bad number"))?)
+            } else {
+                Token::Word(word.to_owned())
+            };
+            tokens.push((start, token));
+        } else {
+            return Err(error(start, &format!("unexpected `{}`", c)));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Quotes `text` as a string of a filter expression.
+pub fn quote(text: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in text.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Parses a decimal or hex integer, or a duration with a unit, in ns, with
+/// an optional `-` in front.
+fn parse_number(word: &str) -> Option<i64> {
+    if let Some(number) = word.strip_prefix('-') {
+        return parse_number(number)?.checked_neg();
+    }
+    if let Some(hex) = word.strip_prefix("0x") {
+        return i64::from_str_radix(hex, 16).ok();
+    }
+    let digits = word
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(word.len());
+    let (number, unit) = word.split_at(digits);
+    let scale = match unit {
+        "" | "ns" => 1.,
+        "us" | "µs" => 1e3,
+        "ms" => 1e6,
+        "s" => 1e9,
+        _ => return None,
+    };
+    if unit.is_empty() {
+        return number.parse().ok();
+    }
+    Some((number.parse::<f64>().ok()? * scale) as i64)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    /// Length of the expression, where errors at its end are.
+    len: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn next(&mut self) -> Result<(usize, Token), ParseError> {
+        let token = self.tokens.get(self.pos).cloned().ok_or(ParseError {
+            position: self.len,
+            message: "unexpected end".to_owned(),
+        })?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        let (position, token) = self.next()?;
+        match token {
+            Token::Not => Ok(Expr::Not(Box::new(self.unary()?))),
+            Token::Open => {
+                let expr = self.or()?;
+                match self.next()? {
+                    (_, Token::Close) => Ok(expr),
+                    (position, token) => Err(ParseError {
+                        position,
+                        message: format!("expected `)`, found {}", token),
+                    }),
+                }
+            }
+            Token::Word(word) => {
+                let (scope, field) = parse_field(&word).ok_or_else(|| ParseError {
+                    position,
+                    message: format!("unknown field `{}`", word),
+                })?;
+                let Some(&Token::Op(op)) = self.peek() else {
+                    return Ok(Expr::Test(scope, field));
+                };
+                self.pos += 1;
+                let literal = match self.next()? {
+                    (_, Token::Number(n)) => Literal::Number(n),
+                    (_, Token::Word(word) | Token::Text(word)) => Literal::Text(word),
+                    (position, token) => {
+                        return Err(ParseError {
+                            position,
+                            message: format!("expected a value, found {}", token),
+                        })
+                    }
+                };
+                Ok(Expr::Compare(scope, field, op, literal))
+            }
+            token => Err(ParseError {
+                position,
+                message: format!("expected a field, found {}", token),
+            }),
+        }
+    }
+}
+
+/// Splits `bio.size` into its scope and field.
+fn parse_field(word: &str) -> Option<(Scope, Field)> {
+    let (scope, rest) = match word.split_once('.') {
+        Some(("bio", rest)) => (Scope::Bio, Some(rest)),
+        Some(("syscall", rest)) => (Scope::Syscall, Some(rest)),
+        Some(_) => return None,
+        None => match word {
+            "bio" => (Scope::Bio, None),
+            "syscall" => (Scope::Syscall, None),
+            _ => (Scope::Any, Some(word)),
+        },
+    };
+    let field = match rest {
+        Some(name) => Field::from_name(name)?,
+        None => Field::Event,
+    };
+    Some((scope, field))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quote, Event, Expr, Filter, Literal, ParseError};
+    use crate::trace::{Bio, FileId, Syscall, SyscallKind, Write};
+
+    fn bio() -> Bio {
+        Bio {
+            offset: 2048,
+            size: 16,
+            is_flush: true,
+            is_write: true,
+            start: 1_000,
+            end: Some(1_501_000),
+            pid: 42,
+            tid: 43,
+            comm: r#"say "hi" \ bye"#.to_owned(),
+            cpu: 1,
+            ..Bio::default()
+        }
+    }
+
+    fn write() -> Syscall {
+        Syscall {
+            kind: SyscallKind::Write(Write {
+                offset: 0,
+                bytes: 4096,
+            }),
+            start: 0,
+            end: Some(2_000_000_000),
+            pid: 42,
+            tid: 42,
+            comm: "postgres".to_owned(),
+            file: Some(FileId {
+                fd: 3,
+                inode: None,
+                path: Some("/data/wal".to_owned()),
+            }),
+            ..Syscall::default()
+        }
+    }
+
+    fn frames() -> Vec<(String, String)> {
+        ["submit_bio_noacct", "submit_bio", "btrfs_sync_file"]
+            .into_iter()
+            .map(|function| (function.to_owned(), "??:0".to_owned()))
+            .collect()
+    }
+
+    fn matches(filter: &str, event: Event) -> bool {
+        Filter::parse(filter).unwrap().matches(event)
+    }
+
+    fn error(filter: &str) -> ParseError {
+        Filter::parse(filter).unwrap_err()
+    }
+
+    #[test]
+    fn durations_and_numbers() {
+        let parsed = |filter: &str| Filter::parse(filter).unwrap();
+        assert_eq!(parsed("latency > 1.5ms"), parsed("latency > 1500000"));
+        assert_eq!(parsed("latency > 5us"), parsed("latency > 5000"));
+        assert_eq!(parsed("latency > 5µs"), parsed("latency > 5000ns"));
+        assert_eq!(parsed("latency > 2s"), parsed("latency > 2000000000"));
+        assert_eq!(parsed("offset == 0x800"), parsed("offset == 2048"));
+
+        let (bio, frames, write) = (bio(), frames(), write());
+        assert!(matches("latency == 1.5ms", Event::Bio(&bio, &frames)));
+        assert!(matches(
+            "latency >= 2s && latency < 2.1s",
+            Event::Syscall(&write)
+        ));
+        assert_eq!(error("latency > 5m").position, 10);
+        assert_eq!(error("latency > 1.5").message, "bad number");
+    }
+
+    #[test]
+    fn negative_numbers() {
+        let parsed = |filter: &str| Filter::parse(filter).unwrap();
+        assert_eq!(parsed("bio.offset > -1"), parsed("bio.offset > -0x1"));
+        assert_eq!(parsed("latency < -1.5ms"), parsed("latency < -1500000"));
+
+        let (mut bio, frames) = (bio(), frames());
+        assert!(matches("bio.offset > -1", Event::Bio(&bio, &frames)));
+        // Completed before it was queued, in a log out of order.
+        bio.end = Some(bio.start - 500);
+        assert!(matches("latency == -500", Event::Bio(&bio, &frames)));
+        assert!(matches(
+            "latency > -1us && latency < 0",
+            Event::Bio(&bio, &frames)
+        ));
+
+        assert_eq!(error("offset > - 1").message, "unexpected `-`");
+        assert_eq!(error("offset > --1").position, 9);
+        assert_eq!(error("offset > -1-").message, "bad number");
+    }
+
+    #[test]
+    fn string_escapes() {
+        let (bio, frames) = (bio(), frames());
+        assert!(matches(
+            r#"comm == "say \"hi\" \\ bye""#,
+            Event::Bio(&bio, &frames)
+        ));
+        assert!(matches(
+            r#"comm contains "\"hi\"""#,
+            Event::Bio(&bio, &frames)
+        ));
+        let unterminated = error(r#"comm == "say \"hi"#);
+        assert_eq!(unterminated.position, 8);
+        assert_eq!(unterminated.message, "unterminated string");
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        let parsed = |filter: &str| Filter::parse(filter).unwrap();
+        assert_eq!(
+            parsed("is_flush || is_write && is_metadata"),
+            parsed("is_flush || (is_write && is_metadata)")
+        );
+        assert_ne!(
+            parsed("is_flush || is_write && is_metadata"),
+            parsed("(is_flush || is_write) && is_metadata")
+        );
+        assert_eq!(
+            parsed("!is_flush && is_write"),
+            parsed("(!is_flush) && is_write")
+        );
+        assert_eq!(parsed("!!is_flush"), parsed("!(!is_flush)"));
+
+        let (bio, frames) = (bio(), frames());
+        let bio = Event::Bio(&bio, &frames);
+        assert!(matches("is_flush || is_write && is_metadata", bio));
+        assert!(!matches("(is_flush || is_write) && is_metadata", bio));
+        assert!(!matches("!is_flush && is_write", bio));
+        assert!(matches("!(is_flush && is_metadata)", bio));
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_token() {
+        let unknown = error("size > 8 && bio.sise > 8");
+        assert_eq!(unknown.position, 12);
+        assert_eq!(unknown.message, "unknown field `bio.sise`");
+        assert_eq!(error("disk.size > 8").position, 0);
+
+        let operator = error("size = 8");
+        assert_eq!(operator.position, 5);
+        assert_eq!(operator.message, "unexpected `=`");
+        assert_eq!(error("size ~ 8").position, 5);
+        assert_eq!(error("size 8").message, "unexpected `8`");
+        assert_eq!(error("(size > 8").position, 9);
+        assert_eq!(error("size >").message, "unexpected end");
+        assert_eq!(
+            error("size > && is_flush").message,
+            "expected a value, found `&&`"
+        );
+    }
+
+    #[test]
+    fn fields_an_event_does_not_have() {
+        let (bio, frames, write) = (bio(), frames(), write());
+        let (bio, write) = (Event::Bio(&bio, &frames), Event::Syscall(&write));
+
+        assert!(!matches("bio.is_flush", write));
+        assert!(matches("!bio.is_flush", write));
+        assert!(!matches("syscall.fd != 3", bio));
+        assert!(!matches("fd == 3 || file contains wal", bio));
+        assert!(matches("fd == 3 && file contains wal", write));
+        assert!(!matches("stack contains submit_bio", write));
+        assert!(matches("bio && pid == 42", bio));
+        assert!(!matches("syscall && pid == 42", bio));
+        assert!(matches("kind == write && bytes == 4096", write));
+
+        let unfinished = Bio {
+            end: None,
+            ..self::bio()
+        };
+        let unfinished = Event::Bio(&unfinished, &[]);
+        assert!(!matches("latency >= 0", unfinished));
+        assert!(!matches("end", unfinished));
+        assert!(matches("!end", unfinished));
+    }
+
+    #[test]
+    fn stack_frames() {
+        let (bio, frames) = (bio(), frames());
+        let bio = Event::Bio(&bio, &frames);
+        assert!(matches("stack contains btrfs_sync", bio));
+        assert!(!matches("stack contains ext4", bio));
+        assert!(!matches("stack == submit_bio", bio));
+
+        assert!(matches("stack under btrfs_sync_file", bio));
+        assert!(matches(r#"stack under "btrfs_sync_file;submit_bio""#, bio));
+        assert!(matches(
+            r#"stack under "btrfs_sync_file;submit_bio;submit_bio_noacct""#,
+            bio
+        ));
+        // Only whole functions on the same path count.
+        assert!(!matches(r#"stack under "btrfs_sync_file;submit""#, bio));
+        assert!(!matches("stack under submit_bio", bio));
+        assert!(!matches(
+            r#"stack under "btrfs_sync_file;submit_bio;submit_bio_noacct;nvme""#,
+            bio
+        ));
+    }
+
+    #[test]
+    fn quoted_strings_parse_back() {
+        for text in ["submit_bio", r#"say "hi""#, r"a\b\", "tab\there", "µs;\n"] {
+            let filter = Filter::parse(&format!("comm == {}", quote(text))).unwrap();
+            let Expr::Compare(_, _, _, Literal::Text(literal)) = filter.expr else {
+                panic!("{:?}", filter);
+            };
+            assert_eq!(literal, text);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Stack traces merged into a tree of frames, for drawing flame graphs.
+
+use std::collections::BTreeMap;
+
+use crate::trace::Bio;
+
+/// What the bios in a flame graph are weighted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Weight {
+    Count,
+    Sectors,
+    /// In ns; bios that did not complete weigh nothing.
+    Latency,
+}
+
+impl Weight {
+    pub fn of(self, bio: &Bio) -> u64 {
+        match self {
+            Weight::Count => 1,
+            Weight::Sectors => bio.size,
+            Weight::Latency => bio.end.map_or(0, |end| (end - bio.start).max(0) as u64),
+        }
+    }
+}
+
+/// A function and the total weight of the stacks that pass through it from
+/// the same callers.
+#[derive(Debug, Clone, Default)]
+pub struct Frame {
+    pub function: String,
+    pub weight: u64,
+    /// Callees, by function.
+    pub children: BTreeMap<String, Frame>,
+}
+
+impl Frame {
+    /// Merges stack traces, each innermost frame first as in
+    /// `TraceData::stack_traces`, under a root frame named `root`.
+    pub fn new<'a>(
+        root: impl Into<String>,
+        stacks: impl IntoIterator<Item = (&'a [(String, String)], u64)>,
+    ) -> Self {
+        let mut frame = Frame {
+            function: root.into(),
+            ..Default::default()
+        };
+        for (stack, weight) in stacks {
+            if weight == 0 {
+                continue;
+            }
+            let mut node = &mut frame;
+            node.weight += weight;
+            for (function, _) in stack.iter().rev() {
+                node = node
+                    .children
+                    .entry(function.clone())
+                    .or_insert_with(|| Frame {
+                        function: function.clone(),
+                        ..Default::default()
+                    });
+                node.weight += weight;
+            }
+        }
+        frame
+    }
+
+    /// Number of frames on the deepest stack, including this one.
+    pub fn depth(&self) -> usize {
+        1 + self.children.values().map(Frame::depth).max().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Frame, Weight};
+    use crate::trace::Bio;
+
+    fn stack(functions: &[&str]) -> Vec<(String, String)> {
+        functions
+            .iter()
+            .map(|function| (function.to_string(), "??:0".to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn merges_stacks_by_caller() {
+        // Innermost first.
+        let a = stack(&["submit_bio", "btrfs_sync_file", "do_fsync"]);
+        let b = stack(&["submit_bio", "ext4_sync_file", "do_fsync"]);
+        let c = stack(&["submit_bio", "btrfs_sync_file", "do_fsync"]);
+        let d = stack(&["blk_flush", "do_fsync"]);
+        let e = stack(&["ignored"]);
+        let root = Frame::new(
+            "all",
+            [
+                (&a[..], 2),
+                (&b[..], 3),
+                (&c[..], 1),
+                (&d[..], 4),
+                (&e[..], 0),
+            ],
+        );
+
+        assert_eq!(root.function, "all");
+        assert_eq!(root.weight, 10);
+        assert_eq!(root.depth(), 4);
+        assert_eq!(root.children.keys().collect::<Vec<_>>(), ["do_fsync"]);
+        let fsync = &root.children["do_fsync"];
+        assert_eq!(fsync.weight, 10);
+        // Callees in order of their names.
+        assert_eq!(
+            fsync.children.keys().collect::<Vec<_>>(),
+            ["blk_flush", "btrfs_sync_file", "ext4_sync_file"]
+        );
+        assert_eq!(fsync.children["btrfs_sync_file"].weight, 3);
+        assert_eq!(
+            fsync.children["btrfs_sync_file"].children["submit_bio"].weight,
+            3
+        );
+        assert_eq!(
+            fsync.children["ext4_sync_file"].children["submit_bio"].weight,
+            3
+        );
+        assert!(fsync.children["blk_flush"].children.is_empty());
+    }
+
+    #[test]
+    fn empty() {
+        let root = Frame::new("all", []);
+        assert_eq!(root.weight, 0);
+        assert_eq!(root.depth(), 1);
+    }
+
+    #[test]
+    fn weights() {
+        let mut bio = Bio {
+            size: 16,
+            is_write: true,
+            start: 1_000,
+            end: Some(3_500),
+            ..Bio::default()
+        };
+        assert_eq!(Weight::Count.of(&bio), 1);
+        assert_eq!(Weight::Sectors.of(&bio), 16);
+        assert_eq!(Weight::Latency.of(&bio), 2_500);
+
+        bio.end = None;
+        assert_eq!(Weight::Latency.of(&bio), 0);
+        assert_eq!(Weight::Count.of(&bio), 1);
+        // Completions from before the queue in a log out of order.
+        bio.end = Some(900);
+        assert_eq!(Weight::Latency.of(&bio), 0);
+
+        let frames = stack(&["submit_bio"]);
+        let root = Frame::new("all", [(&frames[..], Weight::Latency.of(&bio))]);
+        assert!(root.children.is_empty());
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use egui::{Pos2, Rect, Vec2};
+
+/// Rectangles narrower or shorter than this, in pixels, are hit as if they
+/// were this wide or tall.
+const MIN_HIT_SIZE: f32 = 1.;
+
+/// Rectangles on screen bucketed into square cells, so that what is under a
+/// point can be found without looking at every rectangle.
+pub struct Grid<T> {
+    cell_size: f32,
+    /// Only the parts of rectangles inside this are indexed.
+    bounds: Rect,
+    cells: HashMap<(i32, i32), Vec<(Rect, T)>>,
+}
+
+impl<T: Copy> Grid<T> {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            bounds: Rect::NOTHING,
+            cells: HashMap::new(),
+        }
+    }
+
+    /// Removes everything, and indexes `bounds` from now on.
+    pub fn reset(&mut self, bounds: Rect) {
+        self.bounds = bounds;
+        self.cells.clear();
+    }
+
+    fn cell(&self, pos: Pos2) -> (i32, i32) {
+        (
+            (pos.x / self.cell_size).floor() as i32,
+            (pos.y / self.cell_size).floor() as i32,
+        )
+    }
+
+    /// Adds `item` drawn at `rect`. Items added later are on top.
+    pub fn insert(&mut self, rect: Rect, item: T) {
+        // Instantaneous events are drawn as zero-width rectangles, which no
+        // point would be inside.
+        let missing = Vec2::splat(MIN_HIT_SIZE) - rect.size();
+        let rect = rect.expand2(missing.max(Vec2::ZERO) / 2.);
+        let visible = rect.intersect(self.bounds);
+        if visible.min.x > visible.max.x || visible.min.y > visible.max.y {
+            return;
+        }
+        let (min_x, min_y) = self.cell(visible.min);
+        let (max_x, max_y) = self.cell(visible.max);
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                self.cells.entry((x, y)).or_default().push((rect, item));
+            }
+        }
+    }
+
+    /// The topmost item at `pos`.
+    pub fn hit(&self, pos: Pos2) -> Option<T> {
+        self.cells
+            .get(&self.cell(pos))?
+            .iter()
+            .rev()
+            .find(|(rect, _)| rect.contains(pos))
+            .map(|(_, item)| *item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use egui::{Pos2, Rect};
+
+    use super::Grid;
+
+    fn rect(min_x: f32, min_y: f32, max_x: f32, max_y: f32) -> Rect {
+        Rect::from_min_max(Pos2::new(min_x, min_y), Pos2::new(max_x, max_y))
+    }
+
+    fn grid() -> Grid<usize> {
+        let mut grid = Grid::new(10.);
+        grid.reset(rect(0., 0., 100., 100.));
+        grid
+    }
+
+    #[test]
+    fn hits_across_cell_boundaries() {
+        let mut grid = grid();
+        grid.insert(rect(5., 5., 35., 12.), 0);
+        for x in [5., 9.9, 10., 20., 35.] {
+            assert_eq!(grid.hit(Pos2::new(x, 11.)), Some(0), "{}", x);
+        }
+        assert_eq!(grid.hit(Pos2::new(36., 11.)), None);
+        assert_eq!(grid.hit(Pos2::new(20., 13.)), None);
+        // Only the part inside the bounds is indexed.
+        grid.insert(rect(90., 90., 120., 95.), 1);
+        assert_eq!(grid.hit(Pos2::new(95., 92.)), Some(1));
+        assert_eq!(grid.hit(Pos2::new(110., 92.)), None);
+    }
+
+    #[test]
+    fn later_items_are_on_top() {
+        let mut grid = grid();
+        grid.insert(rect(0., 0., 30., 10.), 0);
+        grid.insert(rect(20., 0., 50., 10.), 1);
+        assert_eq!(grid.hit(Pos2::new(10., 5.)), Some(0));
+        assert_eq!(grid.hit(Pos2::new(25., 5.)), Some(1));
+        assert_eq!(grid.hit(Pos2::new(40., 5.)), Some(1));
+    }
+
+    #[test]
+    fn zero_width_rects_are_hit() {
+        let mut grid = grid();
+        grid.insert(rect(20., 0., 20., 10.), 0);
+        grid.insert(rect(0., 50., 100., 50.), 1);
+        assert_eq!(grid.hit(Pos2::new(20.4, 5.)), Some(0));
+        assert_eq!(grid.hit(Pos2::new(19.6, 5.)), Some(0));
+        assert_eq!(grid.hit(Pos2::new(21., 5.)), None);
+        assert_eq!(grid.hit(Pos2::new(60., 50.3)), Some(1));
+    }
+
+    #[test]
+    fn reset_removes_everything() {
+        let mut grid = grid();
+        grid.insert(rect(0., 0., 30., 10.), 0);
+        grid.reset(rect(0., 0., 20., 20.));
+        assert_eq!(grid.hit(Pos2::new(5., 5.)), None);
+        grid.insert(rect(0., 0., 30., 10.), 1);
+        assert_eq!(grid.hit(Pos2::new(5., 5.)), Some(1));
+        assert_eq!(grid.hit(Pos2::new(35., 5.)), None);
+    }
+}
@@ -0,0 +1,210 @@
+//! An index of closed intervals of time, for finding every event that
+//! overlaps a range exactly once.
+//!
+//! This is the implicit augmented interval tree of cgranges: the intervals
+//! are sorted by start and viewed as an in-order binary tree, where the node
+//! at index `i` on level `k` has its lowest `k` bits set, and each node keeps
+//! the largest end in its subtree.
+
+struct Entry<T> {
+    start: i64,
+    end: i64,
+    /// Largest end in the subtree of this entry.
+    max: i64,
+    value: T,
+}
+
+/// Values keyed by closed intervals of time.
+pub struct IntervalIndex<T> {
+    entries: Vec<Entry<T>>,
+    /// Level of the root.
+    max_level: u32,
+}
+
+impl<T> IntervalIndex<T> {
+    /// Indexes `start..=end` intervals. Intervals may be empty (`start ==
+    /// end`), overlap and share starts.
+    pub fn new(intervals: impl IntoIterator<Item = (i64, i64, T)>) -> Self {
+        let mut entries: Vec<Entry<T>> = intervals
+            .into_iter()
+            .map(|(start, end, value)| Entry {
+                start,
+                end,
+                max: end,
+                value,
+            })
+            .collect();
+        entries.sort_by_key(|entry| entry.start);
+        let n = entries.len();
+        if n == 0 {
+            return Self {
+                entries,
+                max_level: 0,
+            };
+        }
+
+        // Leaves, on level 0, are at even indices.
+        let mut last_i = (n - 1) & !1;
+        let mut last = entries[last_i].end;
+        let mut k = 1;
+        while 1 << k <= n {
+            let x = 1 << (k - 1);
+            let step = x << 2;
+            let mut i = (x << 1) - 1;
+            while i < n {
+                let left = entries[i - x].max;
+                let right = if i + x < n { entries[i + x].max } else { last };
+                entries[i].max = entries[i].end.max(left).max(right);
+                i += step;
+            }
+            // The last node on this level, whose max covers the entries
+            // past the end of a complete tree.
+            last_i = if (last_i >> k) & 1 == 1 {
+                last_i - x
+            } else {
+                last_i + x
+            };
+            if last_i < n {
+                last = last.max(entries[last_i].max);
+            }
+            k += 1;
+        }
+        Self {
+            entries,
+            max_level: k - 1,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The values of the intervals that overlap `start..=end`, ordered by
+    /// the start of their interval.
+    pub fn overlapping(&self, start: i64, end: i64) -> impl Iterator<Item = &T> {
+        let n = self.entries.len();
+        let mut found = Vec::new();
+        // Nodes to visit, as level, index and whether the left subtree has
+        // been visited.
+        let mut stack = Vec::new();
+        if n > 0 {
+            stack.push((self.max_level, (1_usize << self.max_level) - 1, false));
+        }
+        while let Some((k, x, left_done)) = stack.pop() {
+            if k <= 3 {
+                // Scan small subtrees.
+                let i0 = x >> k << k;
+                let i1 = (i0 + (1 << (k + 1)) - 1).min(n);
+                for i in i0..i1 {
+                    let entry = &self.entries[i];
+                    if entry.start > end {
+                        break;
+                    }
+                    if entry.end >= start {
+                        found.push(i);
+                    }
+                }
+            } else if !left_done {
+                stack.push((k, x, true));
+                let left = x - (1 << (k - 1));
+                // The left child may be past the end of the entries, and then
+                // still has children before the end.
+                if left >= n || self.entries[left].max >= start {
+                    stack.push((k - 1, left, false));
+                }
+            } else if x < n && self.entries[x].start <= end {
+                if self.entries[x].end >= start {
+                    found.push(x);
+                }
+                stack.push((k - 1, x + (1 << (k - 1)), false));
+            }
+        }
+        found.sort_unstable();
+        found.into_iter().map(|i| &self.entries[i].value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IntervalIndex;
+
+    fn overlapping(index: &IntervalIndex<usize>, start: i64, end: i64) -> Vec<usize> {
+        index.overlapping(start, end).copied().collect()
+    }
+
+    #[test]
+    fn each_overlapping_interval_once() {
+        let index = IntervalIndex::new([(10, 20, 0), (12, 15, 1), (30, 40, 2)]);
+        assert_eq!(overlapping(&index, 0, 100), [0, 1, 2]);
+        assert_eq!(overlapping(&index, 14, 31), [0, 1, 2]);
+        assert_eq!(overlapping(&index, 21, 29), [] as [usize; 0]);
+    }
+
+    #[test]
+    fn interval_spanning_the_range() {
+        let index = IntervalIndex::new([(0, 1000, 0), (5, 6, 1)]);
+        assert_eq!(overlapping(&index, 100, 200), [0]);
+    }
+
+    #[test]
+    fn closed_ends_and_empty_intervals() {
+        let index = IntervalIndex::new([(10, 20, 0), (25, 25, 1)]);
+        assert_eq!(overlapping(&index, 20, 25), [0, 1]);
+        assert_eq!(overlapping(&index, 0, 10), [0]);
+        assert_eq!(overlapping(&index, 25, 25), [1]);
+    }
+
+    #[test]
+    fn shared_starts_are_kept() {
+        let index = IntervalIndex::new([(10, 11, 0), (10, 30, 1), (10, 10, 2)]);
+        assert_eq!(index.len(), 3);
+        let mut found = overlapping(&index, 10, 10);
+        found.sort();
+        assert_eq!(found, [0, 1, 2]);
+    }
+
+    #[test]
+    fn matches_a_linear_scan() {
+        // Deterministic pseudo-random intervals, enough for several levels.
+        let mut seed = 0x2545_f491_u64;
+        let mut next = |bound: i64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % bound as u64) as i64
+        };
+        let intervals: Vec<(i64, i64)> = (0..1000)
+            .map(|_| {
+                let start = next(10_000);
+                (start, start + next(500))
+            })
+            .collect();
+        let index = IntervalIndex::new(
+            intervals
+                .iter()
+                .enumerate()
+                .map(|(i, &(start, end))| (start, end, i)),
+        );
+        for _ in 0..200 {
+            let start = next(11_000) - 500;
+            let end = start + next(1000);
+            let mut found = overlapping(&index, start, end);
+            found.sort();
+            let expected: Vec<usize> = (0..intervals.len())
+                .filter(|&i| intervals[i].0 <= end && intervals[i].1 >= start)
+                .collect();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn empty_index() {
+        let index = IntervalIndex::<usize>::new([]);
+        assert!(index.is_empty());
+        assert_eq!(overlapping(&index, i64::MIN, i64::MAX), [] as [usize; 0]);
+    }
+}
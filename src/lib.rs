@@ -1 +1,11 @@
-pub mod trace;
\ No newline at end of file
+pub mod data;
+pub mod error;
+pub mod filter;
+pub mod flame;
+pub mod interval;
+pub mod load;
+pub mod snapshot;
+pub mod stats;
+pub mod summary;
+pub mod symbolize;
+pub mod trace;
@@ -0,0 +1,43 @@
+//! Reading the files `trace-process` writes.
+
+use std::path::Path;
+
+use crate::error::{Result, TraceError};
+
+/// The frames of a stack trace as function and source location, innermost
+/// first.
+pub type StackTrace = Vec<(String, String)>;
+
+/// Reads `bio.json` or `syscall.json`.
+pub fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T> {
+    let file = std::fs::File::open(path).map_err(|e| TraceError::io(path, e))?;
+    serde_json::from_reader(std::io::BufReader::new(file)).map_err(|e| TraceError::json(path, e))
+}
+
+/// Reads `stack.csv`, indexed by the `stack_trace` of the bios.
+pub fn read_stack_traces(path: &Path) -> Result<Vec<StackTrace>> {
+    let file = std::fs::File::open(path).map_err(|e| TraceError::io(path, e))?;
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(file);
+    let mut stack_traces = Vec::new();
+    for result in reader.records() {
+        let record = result.map_err(|e| TraceError::csv(path, e))?;
+        let line = record.position().map_or(0, |p| p.line());
+        let frames = record
+            .get(1)
+            .ok_or_else(|| TraceError::malformed(path, line, "missing stack trace"))?;
+        let stack_trace: StackTrace = frames
+            .split('\n')
+            .map(|s| {
+                let (function, location) = s.split_once('\t').ok_or_else(|| {
+                    TraceError::malformed(path, line, format!("bad frame {:?}", s))
+                })?;
+                Ok((function.to_owned(), location.to_owned()))
+            })
+            .collect::<Result<_>>()?;
+        stack_traces.push(stack_trace);
+    }
+    Ok(stack_traces)
+}
@@ -0,0 +1,992 @@
+//! Single-pass processing of tracer logs.
+//!
+//! Records of the tracer log start with the event type, the thread id and a
+//! timestamp in ns, followed by the arguments of the event:
+//!
+//! ```text
+//! bio_queue,       tid, ts, sector, sectors, flags (RWFM), stack trace[, dev]
+//! bio_merge,       tid, ts, sector, sectors[, dev]
+//! bio_rq_insert,   tid, ts, sector, sectors[, dev]
+//! bio_rq_issue,    tid, ts, sector, sectors[, dev]
+//! bio_rq_complete, tid, ts, sector, sectors[, dev]
+//! fsync_start,     tid, ts
+//! fsync_end,       tid, ts
+//! write_start,     tid, ts, fd, offset, bytes
+//! write_end,       tid, ts
+//! read_start,      tid, ts, fd, offset, bytes
+//! read_end,        tid, ts
+//! sync_file_range_start, tid, ts, fd, offset, nbytes, flags
+//! sync_file_range_end,   tid, ts
+//! ```
+//!
+//! `pread`, `preadv` and `readahead` are recorded like `read`, and
+//! `fdatasync`, `msync`, `syncfs` and `sync` like `fsync`.
+//!
+//! Start events of syscalls that take a file descriptor may be followed by
+//! the fd, if the syscall has no other arguments, and then by `dev, ino` of
+//! the file, with `dev` in the kernel's encoding:
+//!
+//! ```text
+//! fsync_start,     tid, ts[, fd[, dev, ino]]
+//! write_start,     tid, ts, fd, offset, bytes[, dev, ino]
+//! ```
+//!
+//! After every argument, optional ones included, records may say who caused
+//! the event: the process id, the name of the process and the CPU. Logs
+//! without them get pid and CPU 0 and no process name:
+//!
+//! ```text
+//! bio_queue,       tid, ts, sector, sectors, flags, stack trace, dev[, pid, comm, cpu]
+//! fsync_start,     tid, ts, fd, dev, ino[, pid, comm, cpu]
+//! fsync_end,       tid, ts[, pid, comm, cpu]
+//! ```
+//!
+//! Bios are attributed to the task that queued them, which for writeback is
+//! a kworker rather than the application.
+//!
+//! `dev` is the kernel's `dev_t` of the disk; logs from tracers that do not
+//! record it are treated as coming from a single disk. Devices are named
+//! after the block devices in the kernel snapshot.
+//!
+//! `bio_merge` is a bio being merged into an existing request; the other
+//! request events cover every bio in the request.
+//!
+//! bio.json and syscall.json list the events by the time they started, as
+//! long as the log does: an event is only written out once every event that
+//! started before it has finished.
+
+use std::{
+    cmp::{max, min},
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt::Display,
+    fs::File,
+    io::{BufWriter, Write as _},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use csv::StringRecord;
+use rangemap::RangeSet;
+use serde::Serialize;
+use trace_explorer::{
+    error::{Result, TraceError},
+    snapshot::BlockDevice,
+    trace::{Bio, Dev, FileId, Inode, Read, SyncFileRange, Syscall, SyscallKind, Write},
+};
+
+/// Number of fields of the header every record starts with.
+const HEADER_FIELDS: usize = 3;
+
+/// Bios in flight and syscalls running for longer than this are taken to
+/// have lost their completion or end event. Left in place, a stale bio would
+/// take the sectors of every later completion at its sector, and either would
+/// hold back every later event from the output. The block layer times
+/// requests out well before this.
+const MAX_IN_FLIGHT_NS: i64 = 60_000_000_000;
+
+/// Who caused an event and when.
+#[derive(Debug, PartialEq)]
+struct Header<'a> {
+    tid: u64,
+    timestamp: i64,
+    pid: u64,
+    comm: &'a str,
+    cpu: u32,
+}
+
+/// Number of arguments of each event, optional ones included, or `None` for
+/// events that are not processed.
+fn arguments(event_type: &str) -> Option<usize> {
+    Some(match event_type {
+        "bio_queue" => 5,
+        "bio_merge" | "bio_rq_insert" | "bio_rq_issue" | "bio_rq_complete" => 3,
+        "fsync_start" | "fdatasync_start" | "syncfs_start" => 3,
+        "msync_start" | "sync_start" => 0,
+        "write_start" | "read_start" | "pread_start" | "preadv_start" | "readahead_start" => 5,
+        "sync_file_range_start" => 6,
+        "fsync_end" | "fdatasync_end" | "sync_file_range_end" | "msync_end" | "syncfs_end"
+        | "sync_end" | "write_end" | "read_end" | "pread_end" | "preadv_end"
+        | "readahead_end" => 0,
+        _ => return None,
+    })
+}
+
+impl Header<'_> {
+    fn syscall(&self, kind: SyscallKind, file: Option<FileId>) -> Syscall {
+        Syscall {
+            kind,
+            start: self.timestamp,
+            end: None,
+            pid: self.pid,
+            tid: self.tid,
+            comm: self.comm.to_owned(),
+            cpu: self.cpu,
+            file,
+            stats: None,
+        }
+    }
+}
+
+/// A record of the tracer log, with where it came from for error messages.
+pub struct Record<'a> {
+    log: &'a Path,
+    record: &'a StringRecord,
+}
+
+impl<'a> Record<'a> {
+    pub fn new(log: &'a Path, record: &'a StringRecord) -> Self {
+        Self { log, record }
+    }
+
+    fn malformed(&self, message: impl Into<String>) -> TraceError {
+        let line = self.record.position().map_or(0, |p| p.line());
+        TraceError::malformed(self.log, line, message)
+    }
+
+    fn field(&self, i: usize) -> Result<&'a str> {
+        self.record
+            .get(i)
+            .ok_or_else(|| self.malformed(format!("missing field {}", i)))
+    }
+
+    fn parse_field<T>(&self, i: usize) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let field = self.field(i)?;
+        field
+            .parse()
+            .map_err(|e| self.malformed(format!("field {} ({:?}): {}", i, field, e)))
+    }
+
+    fn event_type(&self) -> Result<&'a str> {
+        self.field(0)
+    }
+
+    /// Reads the header of an event with `args` arguments.
+    fn header(&self, args: usize) -> Result<Header<'a>> {
+        Ok(Header {
+            tid: self.parse_field(1)?,
+            timestamp: self.parse_field(2)?,
+            pid: self.parse_opt(args)?.unwrap_or(0),
+            comm: self.record.get(HEADER_FIELDS + args + 1).unwrap_or(""),
+            cpu: self.parse_opt(args + 2)?.unwrap_or(0),
+        })
+    }
+
+    /// Argument `i` of the event.
+    fn get(&self, i: usize) -> Result<&'a str> {
+        self.field(HEADER_FIELDS + i)
+    }
+
+    fn parse<T>(&self, i: usize) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.parse_field(HEADER_FIELDS + i)
+    }
+
+    /// Parses argument `i` if the record is long enough to have it.
+    fn parse_opt<T>(&self, i: usize) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        if HEADER_FIELDS + i < self.record.len() {
+            self.parse(i).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+struct InFlight {
+    /// Where the bio goes in bio.json.
+    key: StartKey,
+    bio: Bio,
+    /// Sectors completed so far; split bios complete piece by piece.
+    done: u64,
+}
+
+/// Bios that have been queued but not completed yet.
+#[derive(Default)]
+struct InFlightBios {
+    /// Bios with data by device, first sector and queue order.
+    by_sector: BTreeMap<(u32, u64, u64), InFlight>,
+    /// Keys of `by_sector` in queue order, with when the bios were queued.
+    /// Completed bios stay here until they get to the front.
+    queued: VecDeque<(i64, (u32, u64, u64))>,
+    /// Bios without data, i.e. flushes, by device in queue order.
+    empty: HashMap<u32, VecDeque<InFlight>>,
+    /// Size of the largest bio ever queued, which bounds how far before a
+    /// completed range a bio overlapping it can start.
+    max_size: u64,
+    next_seq: u64,
+}
+
+impl InFlightBios {
+    fn insert(&mut self, dev: u32, key: StartKey, bio: Bio) {
+        let in_flight = InFlight { key, bio, done: 0 };
+        if in_flight.bio.size == 0 {
+            self.empty.entry(dev).or_default().push_back(in_flight);
+        } else {
+            self.max_size = max(self.max_size, in_flight.bio.size);
+            let key = (dev, in_flight.bio.offset, self.next_seq);
+            self.queued.push_back((in_flight.bio.start, key));
+            self.by_sector.insert(key, in_flight);
+            self.next_seq += 1;
+        }
+    }
+
+    /// Marks `size` sectors at `offset` of `dev` as completed at
+    /// `timestamp`, and returns the bios that are now complete with their
+    /// keys, or `None` if the completion does not belong to any bio.
+    ///
+    /// A completed request may carry several merged bios, and a split bio
+    /// completes over several requests. When bios overlap, the sectors go to
+    /// the oldest one.
+    fn complete(
+        &mut self,
+        dev: u32,
+        offset: u64,
+        size: u64,
+        timestamp: i64,
+    ) -> Option<Vec<(StartKey, Bio)>> {
+        if size == 0 {
+            let flushes = self.empty.get_mut(&dev)?;
+            let i = flushes.iter().position(|x| x.bio.offset == offset)?;
+            let mut flush = flushes.remove(i)?;
+            flush.bio.end = Some(timestamp);
+            return Some(vec![(flush.key, flush.bio)]);
+        }
+
+        let end = offset + size;
+        let first = offset.saturating_sub(self.max_size);
+        let mut overlapping: Vec<_> = self
+            .by_sector
+            .range((dev, first, 0)..(dev, end, 0))
+            .filter(|(_, x)| x.bio.offset + x.bio.size > offset)
+            .map(|(key, _)| *key)
+            .collect();
+        overlapping.sort_by_key(|&(_, _, seq)| seq);
+
+        let mut taken = RangeSet::new();
+        let mut completed = Vec::new();
+        for key in overlapping {
+            let x = self.by_sector.get_mut(&key).unwrap();
+            let overlap = max(x.bio.offset, offset)..min(x.bio.offset + x.bio.size, end);
+            let sectors: u64 = taken.gaps(&overlap).map(|gap| gap.end - gap.start).sum();
+            if sectors == 0 {
+                continue;
+            }
+            taken.insert(overlap);
+            x.done += sectors;
+            if x.done >= x.bio.size {
+                let mut x = self.by_sector.remove(&key).unwrap();
+                x.bio.end = Some(timestamp);
+                completed.push((x.key, x.bio));
+            }
+        }
+        (!taken.is_empty()).then_some(completed)
+    }
+
+    /// Sets the time of a lifecycle event, picked by `phase`, of the bios in
+    /// flight in `size` sectors at `offset` of `dev` that do not have it yet.
+    /// Returns whether there were any.
+    fn mark(
+        &mut self,
+        dev: u32,
+        offset: u64,
+        size: u64,
+        timestamp: i64,
+        phase: fn(&mut Bio) -> &mut Option<i64>,
+    ) -> bool {
+        if size == 0 {
+            // Flushes go through the request queue one by one.
+            let flushes = self.empty.get_mut(&dev).into_iter().flatten();
+            for flush in flushes {
+                let time = phase(&mut flush.bio);
+                if time.is_none() {
+                    *time = Some(timestamp);
+                    return true;
+                }
+            }
+            return false;
+        }
+
+        let end = offset + size;
+        let first = offset.saturating_sub(self.max_size);
+        let mut marked = false;
+        for (_, x) in self
+            .by_sector
+            .range_mut((dev, first, 0)..(dev, end, 0))
+            .filter(|(_, x)| x.bio.offset + x.bio.size > offset)
+        {
+            let time = phase(&mut x.bio);
+            if time.is_none() {
+                *time = Some(timestamp);
+                marked = true;
+            }
+        }
+        marked
+    }
+
+    /// Removes the bios that have been in flight for longer than
+    /// `MAX_IN_FLIGHT_NS` at `now`.
+    fn evict(&mut self, now: i64) -> Vec<InFlight> {
+        let deadline = now.saturating_sub(MAX_IN_FLIGHT_NS);
+        let mut evicted = Vec::new();
+        while let Some(&(start, key)) = self.queued.front() {
+            if !self.by_sector.contains_key(&key) {
+                self.queued.pop_front();
+            } else if start < deadline {
+                self.queued.pop_front();
+                evicted.extend(self.by_sector.remove(&key));
+            } else {
+                break;
+            }
+        }
+        for flushes in self.empty.values_mut() {
+            while flushes.front().is_some_and(|x| x.bio.start < deadline) {
+                evicted.extend(flushes.pop_front());
+            }
+        }
+        evicted
+    }
+
+    fn len(&self) -> usize {
+        self.by_sector.len() + self.empty.values().map(VecDeque::len).sum::<usize>()
+    }
+
+    fn drain(&mut self) -> impl Iterator<Item = InFlight> + '_ {
+        self.queued.clear();
+        let by_sector = std::mem::take(&mut self.by_sector).into_values();
+        let empty = self.empty.drain().flat_map(|(_, flushes)| flushes);
+        by_sector.chain(empty)
+    }
+}
+
+/// Syscalls that have not returned yet.
+#[derive(Default)]
+struct RunningSyscalls {
+    by_tid: HashMap<u64, (StartKey, Syscall)>,
+    /// Threads of `by_tid` by the keys of their syscalls.
+    by_start: BTreeMap<StartKey, u64>,
+}
+
+impl RunningSyscalls {
+    /// Adds the syscall the thread of `syscall` has entered, and returns the
+    /// one it was in before if that never returned.
+    fn insert(&mut self, key: StartKey, syscall: Syscall) -> Option<(StartKey, Syscall)> {
+        let tid = syscall.tid;
+        self.by_start.insert(key, tid);
+        let unfinished = self.by_tid.insert(tid, (key, syscall))?;
+        self.by_start.remove(&unfinished.0);
+        Some(unfinished)
+    }
+
+    fn remove(&mut self, tid: u64) -> Option<(StartKey, Syscall)> {
+        let running = self.by_tid.remove(&tid)?;
+        self.by_start.remove(&running.0);
+        Some(running)
+    }
+
+    /// Removes the syscalls that have been running for longer than
+    /// `MAX_IN_FLIGHT_NS` at `now`.
+    fn evict(&mut self, now: i64) -> Vec<(StartKey, Syscall)> {
+        let deadline = now.saturating_sub(MAX_IN_FLIGHT_NS);
+        let mut evicted = Vec::new();
+        while let Some(entry) = self.by_start.first_entry() {
+            if entry.key().0 >= deadline {
+                break;
+            }
+            let tid = entry.remove();
+            evicted.extend(self.by_tid.remove(&tid));
+        }
+        evicted
+    }
+
+    fn drain(&mut self) -> impl Iterator<Item = (StartKey, Syscall)> + '_ {
+        self.by_start.clear();
+        self.by_tid.drain().map(|(_, running)| running)
+    }
+}
+
+/// Where an event goes in the output: its start time, then the order it
+/// started in the log.
+type StartKey = (i64, u64);
+
+/// Events that have started, held back until every event that started before
+/// them has finished.
+struct StartOrder<T> {
+    next_seq: u64,
+    /// `None` for the events still in progress.
+    events: BTreeMap<StartKey, Option<T>>,
+}
+
+impl<T> StartOrder<T> {
+    fn new() -> Self {
+        Self {
+            next_seq: 0,
+            events: BTreeMap::new(),
+        }
+    }
+
+    /// Makes room for an event that starts at `start`.
+    fn start(&mut self, start: i64) -> StartKey {
+        let key = (start, self.next_seq);
+        self.next_seq += 1;
+        self.events.insert(key, None);
+        key
+    }
+
+    /// Fills in the event at `key` once it has finished, and returns the
+    /// events that nothing can come before any more, in order.
+    fn finish(&mut self, key: StartKey, event: T) -> Vec<T> {
+        self.events.insert(key, Some(event));
+        let mut ready = Vec::new();
+        while let Some(entry) = self.events.first_entry() {
+            if entry.get().is_none() {
+                break;
+            }
+            ready.extend(entry.remove());
+        }
+        ready
+    }
+}
+
+/// A JSON array that is written out one element at a time.
+struct JsonArray {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    empty: bool,
+}
+
+impl JsonArray {
+    fn create(path: PathBuf) -> Result<Self> {
+        let file = File::create(&path).map_err(|e| TraceError::io(&path, e))?;
+        let mut array = Self {
+            path,
+            writer: BufWriter::new(file),
+            empty: true,
+        };
+        array.write(b"[")?;
+        Ok(array)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer
+            .write_all(bytes)
+            .map_err(|e| TraceError::io(&self.path, e))
+    }
+
+    fn push<T: Serialize>(&mut self, value: &T) -> Result<()> {
+        if !self.empty {
+            self.write(b",")?;
+        }
+        self.empty = false;
+        serde_json::to_writer(&mut self.writer, value).map_err(|e| TraceError::json(&self.path, e))
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.write(b"]")?;
+        self.writer
+            .flush()
+            .map_err(|e| TraceError::io(&self.path, e))
+    }
+}
+
+/// Turns records into bios and syscalls, writing each out to bio.json and
+/// syscall.json as soon as nothing can change it any more.
+///
+/// Only the events that can still be paired with a later one are kept in
+/// memory, so logs of any size can be processed, including ones piped in
+/// from the tracer.
+pub struct Pipeline {
+    /// Names of block devices by major and minor number.
+    device_names: HashMap<(u32, u32), String>,
+    /// Paths of files by major and minor number of their device and inode.
+    file_paths: HashMap<(u32, u32, u64), String>,
+    /// Stack traces seen so far and their ids.
+    stack_traces: HashMap<String, usize>,
+    in_flight_bios: InFlightBios,
+    running_syscalls: RunningSyscalls,
+    /// Bios given up on after `MAX_IN_FLIGHT_NS`.
+    stale_bios: u64,
+    /// Completions that did not belong to any queued bio.
+    unmatched_completions: u64,
+    /// Merge, insert and issue events that did not belong to any queued bio.
+    unmatched_events: u64,
+    bio_order: StartOrder<Bio>,
+    syscall_order: StartOrder<Syscall>,
+    bios: JsonArray,
+    syscalls: JsonArray,
+}
+
+pub struct Finished {
+    /// Stack traces the bios refer to, and their ids.
+    pub stack_traces: HashMap<String, usize>,
+    /// Bios that were queued but never completed.
+    pub unmatched_queues: u64,
+    /// Completions that did not belong to any queued bio.
+    pub unmatched_completions: u64,
+    /// Merge, insert and issue events that did not belong to any queued bio.
+    pub unmatched_events: u64,
+}
+
+impl Pipeline {
+    pub fn create(
+        output_dir: &Path,
+        devices: &[BlockDevice],
+        file_paths: HashMap<(u32, u32, u64), String>,
+    ) -> Result<Self> {
+        Ok(Self {
+            device_names: devices
+                .iter()
+                .map(|d| ((d.major, d.minor), d.name.clone()))
+                .collect(),
+            file_paths,
+            stack_traces: HashMap::new(),
+            in_flight_bios: InFlightBios::default(),
+            running_syscalls: RunningSyscalls::default(),
+            stale_bios: 0,
+            unmatched_completions: 0,
+            unmatched_events: 0,
+            bio_order: StartOrder::new(),
+            syscall_order: StartOrder::new(),
+            bios: JsonArray::create(output_dir.join("bio.json"))?,
+            syscalls: JsonArray::create(output_dir.join("syscall.json"))?,
+        })
+    }
+
+    /// Writes out everything still in flight.
+    pub fn finish(mut self) -> Result<Finished> {
+        let unmatched_queues = self.stale_bios + self.in_flight_bios.len() as u64;
+        let unfinished: Vec<InFlight> = self.in_flight_bios.drain().collect();
+        for x in unfinished {
+            self.write_bio(x.key, x.bio)?;
+        }
+        let running: Vec<(StartKey, Syscall)> = self.running_syscalls.drain().collect();
+        for (key, syscall) in running {
+            self.write_syscall(key, syscall)?;
+        }
+        self.bios.finish()?;
+        self.syscalls.finish()?;
+        Ok(Finished {
+            stack_traces: self.stack_traces,
+            unmatched_queues,
+            unmatched_completions: self.unmatched_completions,
+            unmatched_events: self.unmatched_events,
+        })
+    }
+
+    /// Writes out the bios that have been in flight for too long at `now` to
+    /// still complete.
+    fn evict_stale_bios(&mut self, now: i64) -> Result<()> {
+        for x in self.in_flight_bios.evict(now) {
+            self.stale_bios += 1;
+            self.write_bio(x.key, x.bio)?;
+        }
+        Ok(())
+    }
+
+    /// Writes out the syscalls that have been running for too long at `now`
+    /// to still return, as unfinished.
+    fn evict_stale_syscalls(&mut self, now: i64) -> Result<()> {
+        for (key, syscall) in self.running_syscalls.evict(now) {
+            self.write_syscall(key, syscall)?;
+        }
+        Ok(())
+    }
+
+    fn write_bio(&mut self, key: StartKey, bio: Bio) -> Result<()> {
+        for bio in self.bio_order.finish(key, bio) {
+            self.bios.push(&bio)?;
+        }
+        Ok(())
+    }
+
+    fn write_syscall(&mut self, key: StartKey, syscall: Syscall) -> Result<()> {
+        for syscall in self.syscall_order.finish(key, syscall) {
+            self.syscalls.push(&syscall)?;
+        }
+        Ok(())
+    }
+
+    fn intern_stack_trace(&mut self, stack_trace: &str) -> usize {
+        let next_id = self.stack_traces.len();
+        *self
+            .stack_traces
+            .entry(stack_trace.to_owned())
+            .or_insert(next_id)
+    }
+
+    fn dev(&self, dev: u32) -> Dev {
+        let mut dev = Dev::from_kernel(dev);
+        dev.name = self.device_names.get(&(dev.major, dev.minor)).cloned();
+        dev
+    }
+
+    /// The file of a syscall with `args` arguments, the first being the fd.
+    fn file(&self, record: &Record, args: usize) -> Result<Option<FileId>> {
+        let Some(fd) = record.parse_opt(0)? else {
+            return Ok(None);
+        };
+        let inode = match (record.parse_opt(args)?, record.parse_opt(args + 1)?) {
+            (Some(dev), Some(ino)) => Some(Inode {
+                dev: self.dev(dev),
+                ino,
+            }),
+            _ => None,
+        };
+        let path = inode.as_ref().and_then(|inode| {
+            let key = (inode.dev.major, inode.dev.minor, inode.ino);
+            self.file_paths.get(&key).cloned()
+        });
+        Ok(Some(FileId { fd, inode, path }))
+    }
+
+    fn start_syscall(&mut self, syscall: Syscall) -> Result<()> {
+        let key = self.syscall_order.start(syscall.start);
+        if let Some((key, unfinished)) = self.running_syscalls.insert(key, syscall) {
+            // The end event got lost; the thread has moved on.
+            self.write_syscall(key, unfinished)?;
+        }
+        Ok(())
+    }
+
+    fn end_syscall(&mut self, record: &Record, tid: u64, timestamp: i64) -> Result<()> {
+        let Some((key, mut syscall)) = self.running_syscalls.remove(tid) else {
+            return Ok(());
+        };
+        let event_type = record.event_type()?;
+        if event_type.strip_suffix("_end") != Some(syscall.kind.name()) {
+            let message = format!("{} of thread {} inside {:?}", event_type, tid, syscall.kind);
+            self.running_syscalls.insert(key, syscall);
+            return Err(record.malformed(message));
+        }
+        syscall.end = Some(timestamp);
+        self.write_syscall(key, syscall)
+    }
+
+    pub fn push(&mut self, record: &Record) -> Result<()> {
+        let event_type = record.event_type()?;
+
+        let Some(args) = arguments(event_type) else {
+            return Ok(());
+        };
+        let header = record.header(args)?;
+        let timestamp = header.timestamp;
+        if event_type.starts_with("bio_") {
+            self.evict_stale_bios(timestamp)?;
+        }
+        self.evict_stale_syscalls(timestamp)?;
+
+        match event_type {
+            "bio_queue" => {
+                let flags = record.get(2)?;
+                let dev = record.parse_opt(4)?.unwrap_or(0);
+                let bio = Bio {
+                    dev: self.dev(dev),
+                    offset: record.parse(0)?,
+                    size: record.parse(1)?,
+                    is_metadata: flags.contains("M"),
+                    is_flush: flags.contains("F"),
+                    is_write: flags.contains("W"),
+                    start: timestamp,
+                    merge: None,
+                    insert: None,
+                    issue: None,
+                    end: None,
+                    stack_trace: self.intern_stack_trace(record.get(3)?),
+                    pid: header.pid,
+                    tid: header.tid,
+                    comm: header.comm.to_owned(),
+                    cpu: header.cpu,
+                };
+                let key = self.bio_order.start(bio.start);
+                self.in_flight_bios.insert(dev, key, bio);
+            }
+            "bio_merge" | "bio_rq_insert" | "bio_rq_issue" => {
+                let Ok(offset) = record.parse::<u64>(0) else {
+                    return Ok(());
+                };
+                let size: u64 = record.parse(1)?;
+                let dev = record.parse_opt(2)?.unwrap_or(0);
+                let phase: fn(&mut Bio) -> &mut Option<i64> = match event_type {
+                    "bio_merge" => |bio| &mut bio.merge,
+                    "bio_rq_insert" => |bio| &mut bio.insert,
+                    _ => |bio| &mut bio.issue,
+                };
+                if !self
+                    .in_flight_bios
+                    .mark(dev, offset, size, timestamp, phase)
+                {
+                    self.unmatched_events += 1;
+                }
+            }
+            "bio_rq_complete" => {
+                let Ok(offset) = record.parse::<u64>(0) else {
+                    return Ok(());
+                };
+                let size: u64 = record.parse(1)?;
+                let dev = record.parse_opt(2)?.unwrap_or(0);
+                match self
+                    .in_flight_bios
+                    .complete(dev, offset, size, timestamp)
+                {
+                    Some(completed) => {
+                        for (key, bio) in completed {
+                            self.write_bio(key, bio)?;
+                        }
+                    }
+                    None => self.unmatched_completions += 1,
+                }
+            }
+            "fsync_start" | "fdatasync_start" | "msync_start" | "syncfs_start" | "sync_start" => {
+                let kind = match event_type {
+                    "fsync_start" => SyscallKind::Fsync,
+                    "fdatasync_start" => SyscallKind::Fdatasync,
+                    "msync_start" => SyscallKind::Msync,
+                    "syncfs_start" => SyscallKind::Syncfs,
+                    _ => SyscallKind::Sync,
+                };
+                let file = match kind {
+                    SyscallKind::Msync | SyscallKind::Sync => None,
+                    _ => self.file(record, 1)?,
+                };
+                self.start_syscall(header.syscall(kind, file))?;
+            }
+            "write_start" => {
+                let kind = SyscallKind::Write(Write {
+                    offset: record.parse(1)?,
+                    bytes: record.parse(2)?,
+                });
+                self.start_syscall(header.syscall(kind, self.file(record, 3)?))?;
+            }
+            "sync_file_range_start" => {
+                let kind = SyscallKind::SyncFileRange(SyncFileRange {
+                    offset: record.parse(1)?,
+                    nbytes: record.parse(2)?,
+                    flags: record.parse(3)?,
+                });
+                self.start_syscall(header.syscall(kind, self.file(record, 4)?))?;
+            }
+            "read_start" | "pread_start" | "preadv_start" | "readahead_start" => {
+                let read = Read {
+                    offset: record.parse(1)?,
+                    bytes: record.parse(2)?,
+                };
+                let kind = match event_type {
+                    "read_start" => SyscallKind::Read(read),
+                    "pread_start" => SyscallKind::Pread(read),
+                    "preadv_start" => SyscallKind::Preadv(read),
+                    _ => SyscallKind::Readahead(read),
+                };
+                self.start_syscall(header.syscall(kind, self.file(record, 3)?))?;
+            }
+            "fsync_end" | "fdatasync_end" | "sync_file_range_end" | "msync_end" | "syncfs_end"
+            | "sync_end" | "write_end" | "read_end" | "pread_end" | "preadv_end"
+            | "readahead_end" => self.end_syscall(record, header.tid, timestamp)?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use csv::StringRecord;
+    use trace_explorer::trace::{Bio, Syscall, SyscallKind};
+
+    use super::{
+        arguments, Header, InFlightBios, Record, RunningSyscalls, StartKey, StartOrder,
+        MAX_IN_FLIGHT_NS,
+    };
+
+    fn bio(offset: u64, size: u64, start: i64) -> Bio {
+        Bio {
+            offset,
+            size,
+            is_flush: size == 0,
+            is_write: true,
+            start,
+            ..Bio::default()
+        }
+    }
+
+    /// Queues `bios` on device 0, keyed by their position.
+    fn in_flight(bios: &[(u64, u64, i64)]) -> InFlightBios {
+        let mut in_flight = InFlightBios::default();
+        for (i, &(offset, size, start)) in bios.iter().enumerate() {
+            in_flight.insert(0, (start, i as u64), bio(offset, size, start));
+        }
+        in_flight
+    }
+
+    /// The offsets and completion times of `completed`.
+    fn offsets(completed: Option<Vec<(StartKey, Bio)>>) -> Vec<(u64, Option<i64>)> {
+        completed
+            .expect("completion matches no bio")
+            .into_iter()
+            .map(|(_, bio)| (bio.offset, bio.end))
+            .collect()
+    }
+
+    #[test]
+    fn merged_requests_complete_every_bio() {
+        let mut bios = in_flight(&[(100, 8, 0), (108, 8, 1), (116, 16, 2), (200, 8, 3)]);
+        assert_eq!(
+            offsets(bios.complete(0, 100, 32, 10)),
+            [(100, Some(10)), (108, Some(10)), (116, Some(10))]
+        );
+        assert_eq!(bios.len(), 1);
+        // Another device has the same sectors.
+        assert!(bios.complete(1, 200, 8, 11).is_none());
+    }
+
+    #[test]
+    fn split_bios_complete_with_their_last_piece() {
+        let mut bios = in_flight(&[(100, 32, 0)]);
+        assert_eq!(offsets(bios.complete(0, 100, 16, 10)), []);
+        assert_eq!(offsets(bios.complete(0, 116, 8, 11)), []);
+        assert_eq!(offsets(bios.complete(0, 124, 8, 12)), [(100, Some(12))]);
+        assert_eq!(bios.len(), 0);
+        assert!(bios.complete(0, 100, 8, 13).is_none());
+    }
+
+    #[test]
+    fn overlapping_bios_complete_oldest_first() {
+        let mut bios = in_flight(&[(100, 8, 0), (100, 8, 1)]);
+        let completed = bios.complete(0, 100, 8, 10).unwrap();
+        assert_eq!(completed.len(), 1);
+        assert_eq!(completed[0].0, (0, 0));
+        let completed = bios.complete(0, 96, 16, 11).unwrap();
+        assert_eq!(completed[0].0, (1, 1));
+    }
+
+    #[test]
+    fn flushes_complete_by_offset() {
+        let mut bios = in_flight(&[(0, 0, 0), (5, 0, 1), (100, 8, 2)]);
+        assert_eq!(offsets(bios.complete(0, 5, 0, 10)), [(5, Some(10))]);
+        // A flush that was not queued does not complete another one.
+        assert!(bios.complete(0, 7, 0, 11).is_none());
+        assert!(bios.complete(1, 0, 0, 11).is_none());
+        assert_eq!(offsets(bios.complete(0, 0, 0, 12)), [(0, Some(12))]);
+        assert_eq!(bios.len(), 1);
+    }
+
+    #[test]
+    fn stale_bios_are_evicted() {
+        let mut bios = in_flight(&[
+            (100, 8, 0),
+            (0, 0, 1),
+            (200, 8, 2),
+            (100, 8, MAX_IN_FLIGHT_NS),
+        ]);
+        assert_eq!(offsets(bios.complete(0, 200, 8, 10)), [(200, Some(10))]);
+        assert!(bios.evict(MAX_IN_FLIGHT_NS).is_empty());
+
+        let evicted = bios.evict(MAX_IN_FLIGHT_NS + 2);
+        let mut evicted: Vec<_> = evicted.iter().map(|x| (x.bio.offset, x.bio.end)).collect();
+        evicted.sort();
+        assert_eq!(evicted, [(0, None), (100, None)]);
+        assert_eq!(bios.queued.len(), 1);
+
+        // The sectors go to the bio queued since.
+        let completed = bios.complete(0, 100, 8, MAX_IN_FLIGHT_NS + 3).unwrap();
+        assert_eq!(completed[0].0, (MAX_IN_FLIGHT_NS, 3));
+        assert_eq!(bios.len(), 0);
+    }
+
+    fn fsync(tid: u64, start: i64) -> Syscall {
+        Syscall {
+            kind: SyscallKind::Fsync,
+            start,
+            tid,
+            ..Syscall::default()
+        }
+    }
+
+    #[test]
+    fn stale_syscalls_are_evicted() {
+        let mut running = RunningSyscalls::default();
+        assert!(running.insert((0, 0), fsync(1, 0)).is_none());
+        assert!(running.insert((1, 1), fsync(2, 1)).is_none());
+        assert!(running.insert((2, 2), fsync(3, 2)).is_none());
+        // Thread 2 moved on without returning.
+        let (key, _) = running
+            .insert((MAX_IN_FLIGHT_NS, 3), fsync(2, MAX_IN_FLIGHT_NS))
+            .unwrap();
+        assert_eq!(key, (1, 1));
+        assert!(running.remove(3).is_some());
+        assert!(running.evict(MAX_IN_FLIGHT_NS).is_empty());
+
+        let evicted = running.evict(MAX_IN_FLIGHT_NS + 2);
+        let evicted: Vec<_> = evicted.iter().map(|(key, x)| (*key, x.tid)).collect();
+        assert_eq!(evicted, [((0, 0), 1)]);
+        assert!(running.remove(1).is_none());
+        assert_eq!(running.by_start.len(), 1);
+        assert_eq!(running.remove(2).unwrap().0, (MAX_IN_FLIGHT_NS, 3));
+        assert!(running.by_start.is_empty());
+    }
+
+    #[test]
+    fn start_order_holds_back_events_until_earlier_ones_finish() {
+        let mut order = StartOrder::new();
+        let long = order.start(10);
+        let short = order.start(20);
+        let later = order.start(20);
+        assert!(order.finish(later, "later").is_empty());
+        assert!(order.finish(short, "short").is_empty());
+        assert_eq!(order.finish(long, "long"), ["long", "short", "later"]);
+
+        // Log order does not matter, only the start times.
+        let late = order.start(40);
+        let early = order.start(30);
+        assert!(order.finish(late, "late").is_empty());
+        assert_eq!(order.finish(early, "early"), ["early", "late"]);
+        assert!(order.events.is_empty());
+    }
+
+    fn fields(line: &str) -> StringRecord {
+        StringRecord::from(line.split(',').collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn reads_the_process_after_the_arguments() {
+        let old = fields("bio_queue,7,100,8,16,W,ffff0,8388609");
+        let new = fields("bio_queue,7,100,8,16,W,ffff0,8388609,5,kworker/0:1,3");
+        let args = arguments("bio_queue").unwrap();
+
+        let record = Record::new(Path::new("log.csv"), &old);
+        let header = Header {
+            tid: 7,
+            timestamp: 100,
+            pid: 0,
+            comm: "",
+            cpu: 0,
+        };
+        assert_eq!(record.header(args).unwrap(), header);
+        assert_eq!(record.parse_opt::<u32>(4).unwrap(), Some(8388609));
+
+        let record = Record::new(Path::new("log.csv"), &new);
+        let header = Header {
+            pid: 5,
+            comm: "kworker/0:1",
+            cpu: 3,
+            ..header
+        };
+        assert_eq!(record.header(args).unwrap(), header);
+        assert_eq!(record.parse_opt::<u32>(4).unwrap(), Some(8388609));
+
+        let end = fields("fsync_end,7,100,5,app,3");
+        let record = Record::new(Path::new("log.csv"), &end);
+        assert_eq!(record.header(0).unwrap().comm, "app");
+        assert_eq!(arguments("Attaching 12 probes..."), None);
+    }
+}
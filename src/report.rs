@@ -0,0 +1,326 @@
+//! Summary tables of a processed trace, for comparing runs without the GUI.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use serde::Serialize;
+use trace_explorer::data::TraceData;
+use trace_explorer::stats::LatencySummary;
+
+/// Frames of each top stack trace shown in the text report.
+const TEXT_FRAMES: usize = 8;
+
+#[derive(Serialize)]
+pub struct Report {
+    pub bios: usize,
+    pub syscalls: usize,
+    /// Latency of the completed bios by `read` or `write`, `flush` and
+    /// `metadata`.
+    pub bio_latency: BTreeMap<&'static str, LatencySummary>,
+    /// Latency of the finished syscalls and the bios attributed to them, by
+    /// the name of their kind.
+    pub syscall_kinds: BTreeMap<&'static str, SyscallKindReport>,
+    /// The stack traces that queued the most bios.
+    pub top_stack_traces: Vec<StackTraceReport>,
+}
+
+/// The syscalls of one kind, with the bios that start and end within each
+/// attributed to it.
+#[derive(Serialize)]
+pub struct SyscallKindReport {
+    pub count: usize,
+    pub latency: Option<LatencySummary>,
+    pub flushes: u64,
+    pub write_sectors: u64,
+    pub read_sectors: u64,
+    /// Fraction of the time in the syscalls that bios were in flight, over
+    /// all syscalls of the kind.
+    pub io_time_fraction: f64,
+}
+
+#[derive(Serialize)]
+pub struct StackTraceReport {
+    pub stack_trace: usize,
+    pub bios: u64,
+    pub sectors: u64,
+    /// Functions, innermost first.
+    pub frames: Vec<String>,
+}
+
+impl Report {
+    /// Analyzes every syscall as the explorer does the selected one, and
+    /// keeps the `top` stack traces by number of bios.
+    pub fn new(data: &mut TraceData, top: usize) -> Self {
+        let range = data.range_stats(None);
+        let bio_latency = range
+            .bios
+            .into_iter()
+            .map(|(name, group)| (name, group.summary))
+            .collect();
+
+        let mut syscall_kinds: BTreeMap<&'static str, SyscallKindReport> = BTreeMap::new();
+        // Time in syscalls and with bios in flight, by kind.
+        let mut io_time: HashMap<&'static str, (f64, f64)> = HashMap::new();
+        for idx in 0..data.syscalls().len() {
+            let stats = data.syscall_stats(idx).clone();
+            let syscall = &data.syscalls()[idx];
+            let name = syscall.kind.name();
+            let kind = syscall_kinds.entry(name).or_insert(SyscallKindReport {
+                count: 0,
+                latency: None,
+                flushes: 0,
+                write_sectors: 0,
+                read_sectors: 0,
+                io_time_fraction: 0.,
+            });
+            kind.count += 1;
+            kind.flushes += stats.flushes;
+            kind.write_sectors += stats.write_sectors;
+            kind.read_sectors += stats.read_sectors;
+            let duration = (syscall.end.unwrap_or(syscall.start) - syscall.start) as f64;
+            let (total, busy) = io_time.entry(name).or_default();
+            *total += duration;
+            *busy += stats.frac_io_time * duration;
+        }
+        for (name, group) in range.syscalls {
+            if let Some(kind) = syscall_kinds.get_mut(name) {
+                kind.latency = Some(group.summary);
+            }
+        }
+        for (name, (total, busy)) in io_time {
+            if total > 0. {
+                syscall_kinds.get_mut(name).unwrap().io_time_fraction = busy / total;
+            }
+        }
+
+        let mut by_stack_trace: HashMap<usize, (u64, u64)> = HashMap::new();
+        for bio in data.bios() {
+            let (count, sectors) = by_stack_trace.entry(bio.stack_trace).or_default();
+            *count += 1;
+            *sectors += bio.size;
+        }
+        let mut top_stack_traces: Vec<StackTraceReport> = by_stack_trace
+            .into_iter()
+            .map(|(stack_trace, (bios, sectors))| StackTraceReport {
+                stack_trace,
+                bios,
+                sectors,
+                frames: data
+                    .stack_traces()
+                    .get(stack_trace)
+                    .map_or_else(Vec::new, |frames| {
+                        frames
+                            .iter()
+                            .map(|(function, _)| function.clone())
+                            .collect()
+                    }),
+            })
+            .collect();
+        top_stack_traces.sort_by_key(|report| (std::cmp::Reverse(report.bios), report.stack_trace));
+        top_stack_traces.truncate(top);
+
+        Self {
+            bios: data.bios().len(),
+            syscalls: data.syscalls().len(),
+            bio_latency,
+            syscall_kinds,
+            top_stack_traces,
+        }
+    }
+
+    /// Formats the report as plain-text tables, with latencies in µs.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        let us = |ns: i64| format!("{:.1}", ns as f64 / 1000.);
+        let latency_row = |out: &mut String, name: &str, summary: &LatencySummary| {
+            writeln!(
+                out,
+                "{:<16} {:>8} {:>10.1} {:>10} {:>10} {:>10} {:>10} {:>10}",
+                name,
+                summary.count,
+                summary.mean / 1000.,
+                us(summary.p50),
+                us(summary.p90),
+                us(summary.p99),
+                us(summary.p999),
+                us(summary.max)
+            )
+            .unwrap();
+        };
+        let latency_header = |out: &mut String, title: &str| {
+            writeln!(
+                out,
+                "{:<16} {:>8} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+                title, "count", "mean", "p50", "p90", "p99", "p99.9", "max"
+            )
+            .unwrap();
+        };
+
+        writeln!(out, "{} bios, {} syscalls\n", self.bios, self.syscalls).unwrap();
+
+        latency_header(&mut out, "bio latency µs");
+        for (name, summary) in &self.bio_latency {
+            latency_row(&mut out, name, summary);
+        }
+
+        writeln!(out).unwrap();
+        latency_header(&mut out, "syscall µs");
+        for (name, kind) in &self.syscall_kinds {
+            if let Some(summary) = &kind.latency {
+                latency_row(&mut out, name, summary);
+            }
+        }
+
+        writeln!(out).unwrap();
+        writeln!(
+            out,
+            "{:<16} {:>8} {:>10} {:>14} {:>14} {:>8}",
+            "syscall io", "count", "flushes", "write sectors", "read sectors", "IO time"
+        )
+        .unwrap();
+        for (name, kind) in &self.syscall_kinds {
+            writeln!(
+                out,
+                "{:<16} {:>8} {:>10} {:>14} {:>14} {:>7.2}%",
+                name,
+                kind.count,
+                kind.flushes,
+                kind.write_sectors,
+                kind.read_sectors,
+                kind.io_time_fraction * 100.
+            )
+            .unwrap();
+        }
+
+        writeln!(out).unwrap();
+        writeln!(out, "top stack traces by bios").unwrap();
+        for report in &self.top_stack_traces {
+            writeln!(
+                out,
+                "#{}: {} bios, {} sectors",
+                report.stack_trace, report.bios, report.sectors
+            )
+            .unwrap();
+            for function in report.frames.iter().take(TEXT_FRAMES) {
+                writeln!(out, "    {}", function).unwrap();
+            }
+            if report.frames.len() > TEXT_FRAMES {
+                writeln!(out, "    ...").unwrap();
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use trace_explorer::data::TraceData;
+    use trace_explorer::trace::{Bio, Syscall, SyscallKind};
+
+    use super::{Report, TEXT_FRAMES};
+
+    fn bio(start: i64, end: i64, size: u64, stack_trace: usize) -> Bio {
+        Bio {
+            size,
+            is_write: true,
+            start,
+            end: Some(end),
+            stack_trace,
+            ..Bio::default()
+        }
+    }
+
+    fn fsync(start: i64, end: i64) -> Syscall {
+        Syscall {
+            kind: SyscallKind::Fsync,
+            start,
+            end: Some(end),
+            ..Syscall::default()
+        }
+    }
+
+    fn stack_trace(functions: &[&str]) -> Vec<(String, String)> {
+        functions
+            .iter()
+            .map(|function| (function.to_string(), String::new()))
+            .collect()
+    }
+
+    fn trace() -> TraceData {
+        let deep: Vec<String> = (0..TEXT_FRAMES + 2).map(|i| format!("f{}", i)).collect();
+        let deep: Vec<&str> = deep.iter().map(String::as_str).collect();
+        TraceData::new(
+            vec![
+                bio(10, 60, 8, 1),
+                bio(300, 330, 4, 2),
+                bio(600, 700, 2, 2),
+                bio(800, 900, 1, 1),
+                bio(1000, 1100, 16, 0),
+            ],
+            // In flight for 50 of 100 ns, then for 30 of 300 ns.
+            vec![fsync(0, 100), fsync(200, 500)],
+            vec![
+                stack_trace(&["submit_bio"]),
+                stack_trace(&deep),
+                stack_trace(&["btrfs_sync_file", "submit_bio"]),
+            ],
+        )
+    }
+
+    #[test]
+    fn io_time_is_weighted_by_syscall_duration() {
+        let report = Report::new(&mut trace(), 10);
+        assert_eq!((report.bios, report.syscalls), (5, 2));
+        let fsync = &report.syscall_kinds["fsync"];
+        assert_eq!(fsync.count, 2);
+        assert_eq!(fsync.write_sectors, 12);
+        // 80 of 400 ns, not the mean of 0.5 and 0.1.
+        assert!((fsync.io_time_fraction - 0.2).abs() < 1e-9);
+        assert_eq!(fsync.latency.unwrap().max, 300);
+        assert_eq!(report.bio_latency["write"].count, 5);
+    }
+
+    #[test]
+    fn top_stack_traces_by_bios() {
+        let report = Report::new(&mut trace(), 2);
+        let top: Vec<_> = report
+            .top_stack_traces
+            .iter()
+            .map(|report| (report.stack_trace, report.bios, report.sectors))
+            .collect();
+        // Ties go to the lower index, and the most sectors do not count.
+        assert_eq!(top, [(1, 2, 9), (2, 2, 6)]);
+        assert_eq!(
+            report.top_stack_traces[1].frames,
+            ["btrfs_sync_file", "submit_bio"]
+        );
+
+        let text = report.to_text();
+        assert!(text.contains("#1: 2 bios, 9 sectors\n    f0\n"));
+        assert!(text.contains(&format!("    f{}\n    ...\n", TEXT_FRAMES - 1)));
+        assert!(!text.contains(&format!("f{}", TEXT_FRAMES)));
+        assert!(!text.contains("#0:"));
+    }
+
+    #[test]
+    fn json_shape() {
+        let report = Report::new(&mut trace(), 1);
+        let json = serde_json::to_value(&report).unwrap();
+        let keys: Vec<_> = json.as_object().unwrap().keys().cloned().collect();
+        assert_eq!(
+            keys,
+            [
+                "bio_latency",
+                "bios",
+                "syscall_kinds",
+                "syscalls",
+                "top_stack_traces"
+            ]
+        );
+        assert_eq!(json["bio_latency"]["write"]["p50"], 100);
+        assert_eq!(json["syscall_kinds"]["fsync"]["flushes"], 0);
+        assert_eq!(json["syscall_kinds"]["fsync"]["latency"]["count"], 2);
+        assert_eq!(json["top_stack_traces"][0]["stack_trace"], 1);
+        assert_eq!(json["top_stack_traces"][0]["frames"][0], "f0");
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use itertools::Itertools;
+use object::Object;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Result, TraceError};
+
+/// File name of the snapshot next to a trace.
+pub const SNAPSHOT_FILE: &str = "kernel.json";
+/// File name of the copy of `/proc/kallsyms` next to a trace.
+pub const KALLSYMS_FILE: &str = "kallsyms";
+
+/// What symbolization needs to know about the traced kernel, taken when the
+/// trace is captured.
+///
+/// The layout of a running kernel changes with every boot (KASLR) and every
+/// module load, so it cannot be read back from `/proc` later or elsewhere.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KernelSnapshot {
+    /// `uname -r` of the traced kernel.
+    pub release: String,
+    /// GNU build-id of the traced kernel in hex, if it has one.
+    pub build_id: Option<String>,
+    /// Address of `_stext` in the traced kernel.
+    pub stext: u64,
+    pub modules: Vec<LoadedModule>,
+    /// Block devices, to name the devices bios go to.
+    #[serde(default)]
+    pub block_devices: Vec<BlockDevice>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoadedModule {
+    pub name: String,
+    pub base: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockDevice {
+    pub major: u32,
+    pub minor: u32,
+    pub name: String,
+}
+
+impl KernelSnapshot {
+    /// Takes a snapshot of the running kernel. Addresses are only visible to
+    /// root.
+    pub fn capture() -> Result<Self> {
+        let osrelease = Path::new("/proc/sys/kernel/osrelease");
+        let release = std::fs::read_to_string(osrelease)
+            .map_err(|e| TraceError::io(osrelease, e))?
+            .trim()
+            .to_owned();
+
+        let notes = Path::new("/sys/kernel/notes");
+        let build_id = match std::fs::read(notes) {
+            Ok(notes) => gnu_build_id(&notes),
+            Err(e) => {
+                log::warn!("cannot read the build-id from {}: {}", notes.display(), e);
+                None
+            }
+        };
+
+        let stext = kallsyms_stext(Path::new("/proc/kallsyms"))?;
+
+        let proc_modules = Path::new("/proc/modules");
+        let mut modules = Vec::new();
+        for m in proc_modules::ModuleIter::new().map_err(|e| TraceError::io(proc_modules, e))? {
+            let m = m.map_err(|e| TraceError::io(proc_modules, e))?;
+            if let Some(base) = m.base {
+                modules.push(LoadedModule {
+                    name: m.module,
+                    base,
+                });
+            }
+        }
+
+        Ok(Self {
+            release,
+            build_id,
+            stext,
+            modules,
+            block_devices: block_devices()?,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).map_err(|e| TraceError::io(path, e))?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|e| TraceError::json(path, e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = File::create(path).map_err(|e| TraceError::io(path, e))?;
+        serde_json::to_writer_pretty(file, self).map_err(|e| TraceError::json(path, e))
+    }
+}
+
+/// Lists the block devices of the running system from sysfs.
+fn block_devices() -> Result<Vec<BlockDevice>> {
+    let class = Path::new("/sys/class/block");
+    let mut devices = Vec::new();
+    for entry in std::fs::read_dir(class).map_err(|e| TraceError::io(class, e))? {
+        let entry = entry.map_err(|e| TraceError::io(class, e))?;
+        let dev = entry.path().join("dev");
+        // `major:minor`
+        let Ok(numbers) = std::fs::read_to_string(&dev) else {
+            continue;
+        };
+        let Some((major, minor)) = numbers.trim().split_once(':') else {
+            continue;
+        };
+        let (Ok(major), Ok(minor)) = (major.parse(), minor.parse()) else {
+            continue;
+        };
+        devices.push(BlockDevice {
+            major,
+            minor,
+            name: entry.file_name().to_string_lossy().into_owned(),
+        });
+    }
+    Ok(devices)
+}
+
+/// Reads the address of `_stext` from a copy of `/proc/kallsyms`.
+pub fn kallsyms_stext(kallsyms: &Path) -> Result<u64> {
+    let file = File::open(kallsyms).map_err(|e| TraceError::io(kallsyms, e))?;
+    parse_stext(kallsyms, BufReader::new(file))
+}
+
+/// Finds `_stext` in the lines of the kallsyms at `path`.
+fn parse_stext(path: &Path, kallsyms: impl BufRead) -> Result<u64> {
+    for line in kallsyms.lines() {
+        let line = line.map_err(|e| TraceError::io(path, e))?;
+        let mut parts = line.split_whitespace();
+        if let (Some(addr), Some(_), Some("_stext")) = (parts.next(), parts.next(), parts.next()) {
+            let stext = u64::from_str_radix(addr, 16)
+                .map_err(|e| TraceError::symbolize(path, format!("_stext: {}", e)))?;
+            // Read without root, every address is 0, which would shift all
+            // kernel addresses later.
+            if stext == 0 {
+                return Err(TraceError::symbolize(
+                    path,
+                    "_stext is 0; kernel addresses are only visible to root",
+                ));
+            }
+            return Ok(stext);
+        }
+    }
+    Err(TraceError::symbolize(path, "no _stext symbol"))
+}
+
+/// The GNU build-id of a kernel image, in hex.
+pub fn vmlinux_build_id(vmlinux: &Path) -> Result<Option<String>> {
+    let file = File::open(vmlinux).map_err(|e| TraceError::io(vmlinux, e))?;
+    let cache = object::ReadCache::new(file);
+    let file = object::File::parse(&cache)
+        .map_err(|e| TraceError::symbolize(vmlinux, e.to_string()))?;
+    let build_id = file
+        .build_id()
+        .map_err(|e| TraceError::symbolize(vmlinux, e.to_string()))?;
+    Ok(build_id.map(hex))
+}
+
+/// Finds the GNU build-id in raw ELF notes, like those in
+/// `/sys/kernel/notes`.
+fn gnu_build_id(mut notes: &[u8]) -> Option<String> {
+    const NT_GNU_BUILD_ID: u32 = 3;
+    let align = |n: usize| n.next_multiple_of(4);
+    let word = |bytes: &[u8]| Some(u32::from_ne_bytes(bytes.get(..4)?.try_into().ok()?));
+
+    while notes.len() >= 12 {
+        let name_size = word(&notes[0..])? as usize;
+        let desc_size = word(&notes[4..])? as usize;
+        let kind = word(&notes[8..])?;
+        let name = notes.get(12..12 + name_size)?;
+        let desc_start = 12 + align(name_size);
+        let desc = notes.get(desc_start..desc_start + desc_size)?;
+        if kind == NT_GNU_BUILD_ID && name == b"GNU\0" {
+            return Some(hex(desc));
+        }
+        notes = notes.get(desc_start + align(desc_size)..)?;
+    }
+    None
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).join("")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{gnu_build_id, parse_stext, BlockDevice, KernelSnapshot, LoadedModule};
+
+    const KALLSYMS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/kallsyms");
+
+    /// An ELF note as the kernel lays them out, padded to 4 bytes.
+    fn note(name: &[u8], kind: u32, desc: &[u8]) -> Vec<u8> {
+        let mut note = Vec::new();
+        note.extend((name.len() as u32).to_ne_bytes());
+        note.extend((desc.len() as u32).to_ne_bytes());
+        note.extend(kind.to_ne_bytes());
+        for part in [name, desc] {
+            note.extend(part);
+            note.resize(note.len().next_multiple_of(4), 0);
+        }
+        note
+    }
+
+    #[test]
+    fn finds_the_gnu_build_id() {
+        let mut notes = note(b"Xen\0", 3, &[1, 2, 3, 4, 5]);
+        notes.extend(note(b"Linux\0", 3, &[6]));
+        notes.extend(note(b"GNU\0", 3, &[0xde, 0xad, 0xbe, 0xef, 0x01]));
+        assert_eq!(gnu_build_id(&notes).as_deref(), Some("deadbeef01"));
+
+        assert_eq!(gnu_build_id(&note(b"GNU\0", 1, &[1, 2])), None);
+        assert_eq!(gnu_build_id(&[]), None);
+        // Cut off in the middle of the build-id.
+        assert_eq!(gnu_build_id(&notes[..notes.len() - 6]), None);
+    }
+
+    #[test]
+    fn reads_stext_from_kallsyms() {
+        assert_eq!(
+            super::kallsyms_stext(Path::new(KALLSYMS)).unwrap(),
+            0xffff_ffff_8100_0000
+        );
+        let path = Path::new("kallsyms");
+        let stext = |kallsyms: &str| parse_stext(path, kallsyms.as_bytes());
+        assert_eq!(stext("0 t _text\n10 T _stext\n").unwrap(), 0x10);
+        assert!(stext("ffffffff81000100 t do_one_initcall\n").is_err());
+        assert!(stext("zz T _stext\n").is_err());
+        // As read without root.
+        let hidden = stext("0000000000000000 T _stext\n").unwrap_err();
+        assert!(hidden.to_string().contains("root"), "{}", hidden);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let kernel = KernelSnapshot {
+            release: "6.1.0-test".to_owned(),
+            build_id: Some("deadbeef".to_owned()),
+            stext: 0xffff_ffff_8100_0000,
+            modules: vec![LoadedModule {
+                name: "btrfs".to_owned(),
+                base: 0xffff_ffff_c000_0000,
+            }],
+            block_devices: vec![BlockDevice {
+                major: 259,
+                minor: 2,
+                name: "nvme0n1p2".to_owned(),
+            }],
+        };
+        let path = std::env::temp_dir().join(format!("kernel-{}.json", std::process::id()));
+        kernel.save(&path).unwrap();
+        let loaded = KernelSnapshot::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.release, kernel.release);
+        assert_eq!(loaded.build_id, kernel.build_id);
+        assert_eq!(loaded.stext, kernel.stext);
+        assert_eq!(loaded.modules[0].name, "btrfs");
+        assert_eq!(loaded.modules[0].base, 0xffff_ffff_c000_0000);
+        assert_eq!(loaded.block_devices[0].name, "nvme0n1p2");
+
+        // Snapshots from before block devices were recorded.
+        let old = r#"{"release": "6.1.0", "build_id": null, "stext": 16, "modules": []}"#;
+        let old: KernelSnapshot = serde_json::from_str(old).unwrap();
+        assert!(old.block_devices.is_empty());
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+
+use rangemap::RangeSet;
+use serde::Serialize;
+
+use crate::interval::IntervalIndex;
+use crate::trace::{Bio, Syscall, SyscallStats};
+
+/// The distribution of a set of latencies, in ns.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LatencySummary {
+    pub count: usize,
+    pub min: i64,
+    pub mean: f64,
+    pub p50: i64,
+    pub p90: i64,
+    pub p99: i64,
+    pub p999: i64,
+    pub max: i64,
+}
+
+impl LatencySummary {
+    /// Summarizes `latencies`, or returns `None` if there are none.
+    pub fn new(latencies: impl IntoIterator<Item = i64>) -> Option<Self> {
+        let mut latencies: Vec<i64> = latencies.into_iter().collect();
+        if latencies.is_empty() {
+            return None;
+        }
+        latencies.sort_unstable();
+        let sum: i64 = latencies.iter().sum();
+        Some(Self {
+            count: latencies.len(),
+            min: latencies[0],
+            mean: sum as f64 / latencies.len() as f64,
+            p50: percentile(&latencies, 50.),
+            p90: percentile(&latencies, 90.),
+            p99: percentile(&latencies, 99.),
+            p999: percentile(&latencies, 99.9),
+            max: latencies[latencies.len() - 1],
+        })
+    }
+}
+
+/// The nearest-rank percentile `p` of non-empty, sorted `values`.
+pub fn percentile(values: &[i64], p: f64) -> i64 {
+    let rank = p / 100. * values.len() as f64;
+    // Float error can leave a whole rank such as 99.9% of 1000 just above
+    // itself, where `ceil` would skip to the next one.
+    let rank = (rank - rank * 1e-12).ceil() as usize;
+    values[rank.clamp(1, values.len()) - 1]
+}
+
+/// Attributes to `syscall` the bios that start and end within it, with
+/// `bio_intervals` indexing `bios` by their time span.
+pub fn syscall_stats(
+    bio_intervals: &IntervalIndex<usize>,
+    bios: &[Bio],
+    syscall: &Syscall,
+) -> SyscallStats {
+    let start = syscall.start;
+    let end = syscall.end.unwrap_or(start);
+    let mut stats = SyscallStats {
+        write_sectors: 0,
+        read_sectors: 0,
+        flushes: 0,
+        frac_io_time: 0.,
+    };
+    let mut io_range_set = RangeSet::new();
+    for &idx in bio_intervals.overlapping(start, end) {
+        let bio = &bios[idx];
+        let bio_end = bio.end.unwrap_or(bio.start);
+        if bio.start < start || bio_end > end {
+            continue;
+        }
+        if bio.start < bio_end {
+            io_range_set.insert(bio.start..bio_end);
+        }
+        if bio.is_flush {
+            stats.flushes += 1;
+        }
+        if bio.is_write {
+            stats.write_sectors += bio.size;
+        } else {
+            stats.read_sectors += bio.size;
+        }
+    }
+    if end > start {
+        let io_time: i64 = io_range_set
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum();
+        stats.frac_io_time = io_time as f64 / (end - start) as f64;
+    }
+    stats
+}
+
+/// Counts of latencies in buckets that double in width: bucket `i` holds the
+/// latencies in `2^i..2^(i + 1)` ns, except that bucket 0 starts at 0 ns and
+/// also holds the negative latencies of events logged out of order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Histogram {
+    pub counts: Vec<u64>,
+}
+
+impl Histogram {
+    pub fn new(latencies: impl IntoIterator<Item = i64>) -> Self {
+        let mut counts = Vec::new();
+        for latency in latencies {
+            let bucket = Self::bucket(latency);
+            if counts.len() <= bucket {
+                counts.resize(bucket + 1, 0);
+            }
+            counts[bucket] += 1;
+        }
+        Self { counts }
+    }
+
+    pub fn bucket(latency: i64) -> usize {
+        latency.max(1).ilog2() as usize
+    }
+
+    /// The latencies in `bucket`, as a half-open range in ns.
+    pub fn bounds(bucket: usize) -> (i64, i64) {
+        let low = if bucket == 0 { 0 } else { 1 << bucket };
+        (low, (1_i64 << bucket).saturating_mul(2))
+    }
+
+    /// The buckets from the first to the last that are not empty.
+    pub fn occupied(&self) -> std::ops::Range<usize> {
+        let first = self.counts.iter().position(|&count| count > 0);
+        first.map_or(0..0, |first| first..self.counts.len())
+    }
+}
+
+/// The latencies of a group of events.
+#[derive(Debug, Clone)]
+pub struct LatencyGroup {
+    pub summary: LatencySummary,
+    pub histogram: Histogram,
+    /// The events, as an index into their list and their latency.
+    pub events: Vec<(usize, i64)>,
+}
+
+impl LatencyGroup {
+    /// Summarizes `events`, or returns `None` if there are none.
+    pub fn new(events: Vec<(usize, i64)>) -> Option<Self> {
+        Some(Self {
+            summary: LatencySummary::new(events.iter().map(|&(_, latency)| latency))?,
+            histogram: Histogram::new(events.iter().map(|&(_, latency)| latency)),
+            events,
+        })
+    }
+}
+
+/// Groups the latencies of the completed bios into `read` or `write`, and
+/// also `flush` and `metadata` for bios that are.
+pub fn bio_latency_groups<'a>(
+    bios: impl IntoIterator<Item = (usize, &'a Bio)>,
+) -> BTreeMap<&'static str, LatencyGroup> {
+    let mut latencies: BTreeMap<&'static str, Vec<(usize, i64)>> = BTreeMap::new();
+    for (idx, bio) in bios {
+        let Some(end) = bio.end else {
+            continue;
+        };
+        let event = (idx, end - bio.start);
+        let direction = if bio.is_write { "write" } else { "read" };
+        latencies.entry(direction).or_default().push(event);
+        if bio.is_flush {
+            latencies.entry("flush").or_default().push(event);
+        }
+        if bio.is_metadata {
+            latencies.entry("metadata").or_default().push(event);
+        }
+    }
+    latencies
+        .into_iter()
+        .filter_map(|(name, events)| Some((name, LatencyGroup::new(events)?)))
+        .collect()
+}
+
+/// Groups the latencies of the finished syscalls by the name of their kind.
+pub fn syscall_latency_groups<'a>(
+    syscalls: impl IntoIterator<Item = (usize, &'a Syscall)>,
+) -> BTreeMap<&'static str, LatencyGroup> {
+    let mut latencies: BTreeMap<&'static str, Vec<(usize, i64)>> = BTreeMap::new();
+    for (idx, syscall) in syscalls {
+        if let Some(end) = syscall.end {
+            latencies
+                .entry(syscall.kind.name())
+                .or_default()
+                .push((idx, end - syscall.start));
+        }
+    }
+    latencies
+        .into_iter()
+        .filter_map(|(name, events)| Some((name, LatencyGroup::new(events)?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Histogram, LatencySummary};
+
+    #[test]
+    fn histogram_buckets() {
+        for (latency, bucket) in [
+            (-5, 0),
+            (0, 0),
+            (1, 0),
+            (2, 1),
+            (3, 1),
+            (4, 2),
+            (1023, 9),
+            (1024, 10),
+            (i64::MAX, 62),
+        ] {
+            assert_eq!(Histogram::bucket(latency), bucket, "{}", latency);
+            let (low, high) = Histogram::bounds(bucket);
+            assert!(latency.max(0) >= low && (latency < high || high == i64::MAX));
+        }
+        assert_eq!(Histogram::bounds(0), (0, 2));
+        assert_eq!(Histogram::bounds(1), (2, 4));
+        assert_eq!(Histogram::bounds(10), (1024, 2048));
+        assert_eq!(Histogram::bounds(62), (1 << 62, i64::MAX));
+    }
+
+    #[test]
+    fn histogram_occupied() {
+        assert_eq!(Histogram::new([]).occupied(), 0..0);
+        let histogram = Histogram::new([5, 100, 6, 7]);
+        assert_eq!(histogram.counts, [0, 0, 3, 0, 0, 0, 1]);
+        assert_eq!(histogram.occupied(), 2..7);
+        assert_eq!(Histogram::new([0]).occupied(), 0..1);
+    }
+
+    #[test]
+    fn percentiles_of_few_latencies() {
+        assert_eq!(LatencySummary::new([]), None);
+
+        let one = LatencySummary::new([7]).unwrap();
+        assert_eq!((one.min, one.p50, one.p999, one.max), (7, 7, 7, 7));
+
+        let ten = LatencySummary::new((1..=10).rev()).unwrap();
+        assert_eq!(ten.count, 10);
+        assert_eq!(ten.mean, 5.5);
+        assert_eq!((ten.p50, ten.p90, ten.p99, ten.p999), (5, 9, 10, 10));
+
+        let thousand = LatencySummary::new(1..=1000).unwrap();
+        assert_eq!(
+            (thousand.p99, thousand.p999, thousand.max),
+            (990, 999, 1000)
+        );
+        let more = LatencySummary::new(1..=1001).unwrap();
+        assert_eq!(more.p999, 1000);
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+
+/// Aggregate of the events in a span of time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Bin {
+    /// Events that start in the span.
+    pub count: u64,
+    /// Bytes of the events that start in the span.
+    pub bytes: u64,
+    /// Time in the span, in ns, during which at least one event was running.
+    pub busy: i64,
+}
+
+impl Bin {
+    fn add(&mut self, other: &Bin) {
+        self.count += other.count;
+        self.bytes += other.bytes;
+        self.busy += other.busy;
+    }
+
+    /// The share of a bin `width` ns wide that is from `from` to `to` ns
+    /// into it. Shares of adjacent spans add up exactly to the share of both.
+    fn share(&self, from: i64, to: i64, width: i64) -> Bin {
+        let up_to =
+            |value: u64, t: i64| (value as u128 * t.clamp(0, width) as u128 / width as u128) as u64;
+        let share = |value: u64| up_to(value, to) - up_to(value, from);
+        Bin {
+            count: share(self.count),
+            bytes: share(self.bytes),
+            busy: share(self.busy as u64) as i64,
+        }
+    }
+}
+
+/// Events in bins of one width, leaving out empty bins.
+struct Level {
+    width: i64,
+    /// Sorted by bin number; bin `i` covers `i * width..(i + 1) * width`.
+    bins: Vec<(i64, Bin)>,
+}
+
+/// Events binned at resolutions from [`Summary::FINEST`] ns up to the whole
+/// trace, each level twice as coarse as the one before, for drawing a trace
+/// that is zoomed out too far to draw its events one by one.
+///
+/// Long traces start at coarser bins, so that the finest level has at most
+/// about [`Summary::MAX_BINS`] bins whatever the length of the trace.
+pub struct Summary {
+    levels: Vec<Level>,
+}
+
+impl Summary {
+    /// Width of the bins of the finest level, in ns.
+    pub const FINEST: i64 = 1 << 16;
+    /// Bins the finest level covers the trace with at most, not counting
+    /// partial bins at its ends.
+    pub const MAX_BINS: i64 = 1 << 18;
+
+    /// Summarizes events given as start, end and bytes.
+    pub fn new(events: impl IntoIterator<Item = (i64, i64, u64)>) -> Self {
+        let mut events: Vec<(i64, i64, u64)> = events.into_iter().collect();
+        events.sort_unstable_by_key(|&(start, _, _)| start);
+
+        let span = match events.first() {
+            Some(&(first, _, _)) => events.iter().map(|&(_, end, _)| end).max().unwrap() - first,
+            None => 0,
+        };
+        let mut width = Self::FINEST;
+        while span / width > Self::MAX_BINS {
+            width *= 2;
+        }
+        let mut bins: BTreeMap<i64, Bin> = BTreeMap::new();
+        for &(start, _, bytes) in &events {
+            let bin = bins.entry(start.div_euclid(width)).or_default();
+            bin.count += 1;
+            bin.bytes += bytes;
+        }
+
+        // Busy time is the union of the events, so overlapping events are
+        // merged before they are split into bins.
+        let mut add_busy = |start: i64, end: i64| {
+            let mut t = start;
+            while t < end {
+                let i = t.div_euclid(width);
+                let bin_end = (i + 1) * width;
+                bins.entry(i).or_default().busy += end.min(bin_end) - t;
+                t = bin_end;
+            }
+        };
+        let mut running: Option<(i64, i64)> = None;
+        for &(start, end, _) in &events {
+            running = match running {
+                Some((run_start, run_end)) if start <= run_end => {
+                    Some((run_start, run_end.max(end)))
+                }
+                Some((run_start, run_end)) => {
+                    add_busy(run_start, run_end);
+                    Some((start, end))
+                }
+                None => Some((start, end)),
+            };
+        }
+        if let Some((run_start, run_end)) = running {
+            add_busy(run_start, run_end);
+        }
+
+        let bins = bins.into_iter().collect();
+        let mut levels = vec![Level { width, bins }];
+        while levels.last().unwrap().bins.len() > 1 {
+            let finer = levels.last().unwrap();
+            let mut bins: Vec<(i64, Bin)> = Vec::new();
+            for (i, bin) in &finer.bins {
+                let parent = i.div_euclid(2);
+                match bins.last_mut() {
+                    Some((last, coarse)) if *last == parent => coarse.add(bin),
+                    _ => bins.push((parent, *bin)),
+                }
+            }
+            levels.push(Level {
+                width: finer.width * 2,
+                bins,
+            });
+        }
+        Self { levels }
+    }
+
+    /// Aggregates the events in `start..end`, using bins no wider than
+    /// `resolution` ns where possible. Bins that straddle the ends of the
+    /// range count in proportion to how much of them is in it, so queries of
+    /// adjacent ranges add up to the query of both.
+    pub fn query(&self, start: i64, end: i64, resolution: i64) -> Bin {
+        let level = self
+            .levels
+            .iter()
+            .rev()
+            .find(|level| level.width <= resolution)
+            .or(self.levels.first());
+        let mut total = Bin::default();
+        let Some(level) = level else {
+            return total;
+        };
+        let first = start.div_euclid(level.width);
+        let last = (end - 1).div_euclid(level.width);
+        let at = level.bins.partition_point(|&(i, _)| i < first);
+        for (i, bin) in level.bins[at..].iter().take_while(|&&(i, _)| i <= last) {
+            let bin_start = i * level.width;
+            total.add(&bin.share(start - bin_start, end - bin_start, level.width));
+        }
+        total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bin, Summary};
+
+    fn summary() -> Summary {
+        Summary::new([
+            (0, 10_000, 4096),
+            (5_000, 200_000, 512),
+            (70_000, 70_500, 8192),
+            (300_000, 1_000_000, 4096),
+            (999_999, 1_000_001, 1),
+        ])
+    }
+
+    #[test]
+    fn whole_trace() {
+        let total = summary().query(0, 2_000_000, i64::MAX);
+        assert_eq!(total.count, 5);
+        assert_eq!(total.bytes, 4096 + 512 + 8192 + 4096 + 1);
+        assert_eq!(total.busy, 200_000 + 700_001);
+    }
+
+    #[test]
+    fn columns_add_up_to_their_range() {
+        let summary = summary();
+        for (ns_per_pixel, start) in [
+            (100_000, 0),
+            (100_000, 12_345),
+            (30_000, -50_000),
+            (1 << 20, 7),
+        ] {
+            let mut columns = Bin::default();
+            for x in 0..40 {
+                let from = start + x * ns_per_pixel;
+                let column = summary.query(from, from + ns_per_pixel, ns_per_pixel);
+                assert!(column.busy <= ns_per_pixel, "{:?}", column);
+                columns.add(&column);
+            }
+            let range = summary.query(start, start + 40 * ns_per_pixel, ns_per_pixel);
+            assert_eq!(
+                columns, range,
+                "{} ns per pixel from {}",
+                ns_per_pixel, start
+            );
+        }
+    }
+
+    #[test]
+    fn long_traces_get_coarser_bins() {
+        let hour = 3_600_000_000_000;
+        let long = Summary::new([(0, hour, 4096), (hour / 2, hour / 2 + 1, 512)]);
+        let finest = &long.levels[0];
+        assert!(finest.width > Summary::FINEST);
+        assert!(finest.bins.len() as i64 <= Summary::MAX_BINS + 2);
+        let total = long.query(0, 2 * hour, 1);
+        assert_eq!((total.count, total.busy), (2, hour));
+
+        assert_eq!(summary().levels[0].width, Summary::FINEST);
+    }
+}
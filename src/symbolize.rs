@@ -0,0 +1,586 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    rc::Rc,
+};
+
+use itertools::Itertools;
+use object::{CompressionFormat, Object, ObjectSection};
+use serde::Deserialize;
+
+use crate::error::{Result, TraceError};
+
+/// A kernel object that stack trace addresses fall into.
+#[derive(Debug, Clone)]
+pub struct KernelObject {
+    /// `vmlinux` or the name of a module.
+    pub name: String,
+    /// Where the debuginfo of the object is expected.
+    pub path: PathBuf,
+}
+
+/// A way of turning addresses into frames.
+pub trait Symbolize {
+    /// Resolves `addrs`, given in the address space of `object`. Addresses
+    /// that cannot be resolved at all are left out of the result.
+    fn symbolize(&mut self, object: &KernelObject, addrs: &[u64])
+        -> Result<HashMap<u64, Vec<Frame>>>;
+}
+
+/// A function in the call chain of an address. Inlined functions get a frame
+/// of their own.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub function: String,
+    /// `file:line`, with `??` for whatever is unknown, like addr2line prints it.
+    pub location: String,
+}
+
+impl Frame {
+    fn unknown() -> Self {
+        Self {
+            function: "??".to_owned(),
+            location: "??:0".to_owned(),
+        }
+    }
+}
+
+/// Relocations of a debug section.
+///
+/// Kernel modules are relocatable objects, so references from their DWARF
+/// into the code only become addresses once the relocations are applied.
+#[derive(Debug, Clone, Default)]
+struct Relocations(Rc<object::read::RelocationMap>);
+
+impl gimli::Relocate for Relocations {
+    fn relocate_address(&self, offset: usize, value: u64) -> gimli::Result<u64> {
+        Ok(self.0.relocate(offset as u64, value))
+    }
+
+    fn relocate_offset(&self, offset: usize, value: usize) -> gimli::Result<usize> {
+        <usize as gimli::ReaderOffset>::from_u64(self.0.relocate(offset as u64, value as u64))
+    }
+}
+
+type Reader = gimli::RelocateReader<gimli::EndianRcSlice<gimli::RunTimeEndian>, Relocations>;
+
+struct Loaded {
+    context: addr2line::Context<Reader>,
+}
+
+/// Resolves addresses in kernel objects (vmlinux and `.ko` files) to
+/// functions and source lines using their debuginfo.
+///
+/// Parsing the debuginfo of vmlinux takes a while, so every object is loaded
+/// once and kept around for later lookups.
+#[derive(Default)]
+pub struct Symbolizer {
+    objects: HashMap<PathBuf, Loaded>,
+}
+
+impl Symbolizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn load(&mut self, object: &Path) -> Result<&Loaded> {
+        if !self.objects.contains_key(object) {
+            let loaded = load(object)?;
+            self.objects.insert(object.to_owned(), loaded);
+        }
+        Ok(&self.objects[object])
+    }
+
+    /// Resolves `addr`, given in the address space of `object`, innermost
+    /// inlined function first.
+    pub fn resolve(&mut self, object: &Path, addr: u64) -> Result<Vec<Frame>> {
+        let loaded = self.load(object)?;
+        let error = |e: gimli::Error| TraceError::symbolize(object, format!("{:#x}: {}", addr, e));
+
+        let mut iter = loaded
+            .context
+            .find_frames(addr)
+            .skip_all_loads()
+            .map_err(error)?;
+        let mut frames = Vec::new();
+        while let Some(frame) = iter.next().map_err(error)? {
+            let function = match &frame.function {
+                Some(name) => name.demangle().map_err(error)?.into_owned(),
+                None => "??".to_owned(),
+            };
+            let location = match &frame.location {
+                Some(location) => format!(
+                    "{}:{}",
+                    location.file.unwrap_or("??"),
+                    location
+                        .line
+                        .map_or_else(|| "?".to_owned(), |line| line.to_string())
+                ),
+                None => "??:0".to_owned(),
+            };
+            frames.push(Frame { function, location });
+        }
+        if frames.is_empty() {
+            frames.push(Frame::unknown());
+        }
+        Ok(frames)
+    }
+}
+
+impl Symbolize for Symbolizer {
+    fn symbolize(
+        &mut self,
+        object: &KernelObject,
+        addrs: &[u64],
+    ) -> Result<HashMap<u64, Vec<Frame>>> {
+        // A missing or broken module should not keep the rest of the trace
+        // from being symbolized.
+        if let Err(e) = self.load(&object.path) {
+            eprintln!("warning: {}, leaving its addresses unresolved", e);
+            return Ok(HashMap::new());
+        }
+        addrs
+            .iter()
+            .map(|&addr| Ok((addr, self.resolve(&object.path, addr)?)))
+            .collect()
+    }
+}
+
+/// The address of section `name` in `object`, if it has one.
+pub fn section_address(object: &Path, name: &str) -> Result<Option<u64>> {
+    let file = File::open(object).map_err(|e| TraceError::io(object, e))?;
+    // Only read the headers, not all of vmlinux.
+    let cache = object::ReadCache::new(file);
+    let file = object::File::parse(&cache)
+        .map_err(|e| TraceError::symbolize(object, e.to_string()))?;
+    Ok(file.section_by_name(name).map(|section| section.address()))
+}
+
+fn load(path: &Path) -> Result<Loaded> {
+    let data: Rc<[u8]> = std::fs::read(path)
+        .map_err(|e| TraceError::io(path, e))?
+        .into();
+    let file = object::File::parse(&*data)
+        .map_err(|e| TraceError::symbolize(path, e.to_string()))?;
+    let endian = if file.is_little_endian() {
+        gimli::RunTimeEndian::Little
+    } else {
+        gimli::RunTimeEndian::Big
+    };
+    let whole = gimli::EndianRcSlice::new(data.clone(), endian);
+
+    let load_section = |id: gimli::SectionId| -> object::Result<Reader> {
+        let Some(section) = file.section_by_name(id.name()) else {
+            return Ok(Reader::new(whole.range(0..0), Relocations::default()));
+        };
+        let range = section.compressed_file_range()?;
+        let bytes = if range.format == CompressionFormat::None {
+            // Share the uncompressed sections with the file instead of
+            // copying them.
+            let start = range.offset as usize;
+            whole.range(start..start + range.uncompressed_size as usize)
+        } else {
+            let data: Cow<[u8]> = section.uncompressed_data()?;
+            gimli::EndianRcSlice::new(Rc::from(&*data), endian)
+        };
+        let relocations = Relocations(Rc::new(section.relocation_map()?));
+        Ok(Reader::new(bytes, relocations))
+    };
+    let dwarf = gimli::Dwarf::load(load_section)
+        .map_err(|e| TraceError::symbolize(path, e.to_string()))?;
+    let context = addr2line::Context::from_dwarf(dwarf)
+        .map_err(|e| TraceError::symbolize(path, e.to_string()))?;
+
+    Ok(Loaded { context })
+}
+
+/// Runs GNU addr2line and parses its text output.
+pub struct Addr2line;
+
+impl Symbolize for Addr2line {
+    fn symbolize(
+        &mut self,
+        object: &KernelObject,
+        addrs: &[u64],
+    ) -> Result<HashMap<u64, Vec<Frame>>> {
+        let path = &object.path;
+        let output = Command::new("addr2line")
+            .arg("--functions")
+            .arg("--inlines")
+            .arg("--addresses")
+            .arg("-e")
+            .arg(path)
+            .args(addrs.iter().map(|x| format!("{:#x}", x)))
+            .output()
+            .map_err(|e| TraceError::symbolize(path, format!("cannot run addr2line: {}", e)))?;
+        check_status(path, "addr2line", &output)?;
+        parse_addr2line(path, &String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+/// Fails if a symbolizer run on `path` did not exit successfully, with what
+/// it printed to stderr.
+fn check_status(path: &Path, program: &str, output: &Output) -> Result<()> {
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(TraceError::symbolize(
+        path,
+        format!(
+            "{} {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    ))
+}
+
+/// Parses the output of `addr2line --functions --inlines --addresses`: each
+/// address on a line of its own, followed by a function and a location line
+/// for every frame.
+fn parse_addr2line(path: &Path, output: &str) -> Result<HashMap<u64, Vec<Frame>>> {
+    let unexpected = |line: Option<&str>| {
+        TraceError::symbolize(
+            path,
+            format!("unexpected addr2line output {:?}", line.unwrap_or("<EOF>")),
+        )
+    };
+
+    let mut resolved = HashMap::new();
+    let mut lines = output.lines().peekable();
+    while let Some(line) = lines.next() {
+        let addr = line
+            .strip_prefix("0x")
+            .and_then(|x| u64::from_str_radix(x, 16).ok())
+            .ok_or_else(|| unexpected(Some(line)))?;
+
+        let mut frames = Vec::new();
+        while let Some(next_line) = lines.peek() {
+            if next_line.starts_with("0x") {
+                break;
+            }
+            let function = lines.next().ok_or_else(|| unexpected(None))?;
+            let location = lines.next().ok_or_else(|| unexpected(None))?;
+            frames.push(Frame {
+                function: function.to_owned(),
+                location: location.to_owned(),
+            });
+        }
+        resolved.insert(addr, frames);
+    }
+    Ok(resolved)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Symbol {
+    #[serde(default)]
+    column: u32,
+    #[serde(default)]
+    file_name: String,
+    #[serde(default)]
+    function_name: String,
+    #[serde(default)]
+    line: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct LlvmSymbolizerItem {
+    address: String,
+    #[serde(default)]
+    symbol: Vec<Symbol>,
+}
+
+impl LlvmSymbolizerItem {
+    fn address_(&self) -> Option<u64> {
+        let stripped = self.address.strip_prefix("0x")?;
+        u64::from_str_radix(stripped, 16).ok()
+    }
+}
+
+/// Runs `llvm-symbolizer --output-style=JSON`.
+pub struct LlvmSymbolizer;
+
+impl Symbolize for LlvmSymbolizer {
+    fn symbolize(
+        &mut self,
+        object: &KernelObject,
+        addrs: &[u64],
+    ) -> Result<HashMap<u64, Vec<Frame>>> {
+        let path = &object.path;
+        let output = Command::new("llvm-symbolizer")
+            .arg("--output-style=JSON")
+            .arg("--obj")
+            .arg(path)
+            .args(addrs.iter().map(|x| format!("{:#x}", x)))
+            .output()
+            .map_err(|e| {
+                TraceError::symbolize(path, format!("cannot run llvm-symbolizer: {}", e))
+            })?;
+        check_status(path, "llvm-symbolizer", &output)?;
+        parse_llvm_symbolizer(path, &output.stdout)
+    }
+}
+
+/// Parses the output of `llvm-symbolizer --output-style=JSON`.
+fn parse_llvm_symbolizer(path: &Path, output: &[u8]) -> Result<HashMap<u64, Vec<Frame>>> {
+    let items: Vec<LlvmSymbolizerItem> = serde_json::from_slice(output)
+        .map_err(|e| TraceError::symbolize(path, format!("llvm-symbolizer output: {}", e)))?;
+
+    let mut resolved = HashMap::new();
+    for item in items {
+        let addr = item.address_().ok_or_else(|| {
+            TraceError::symbolize(path, format!("bad address {:?}", item.address))
+        })?;
+        let frames = item
+            .symbol
+            .iter()
+            .map(|x| Frame {
+                function: x.function_name.clone(),
+                location: format!("{}:{}:{}", x.file_name, x.line, x.column),
+            })
+            .collect();
+        resolved.insert(addr, frames);
+    }
+    Ok(resolved)
+}
+
+/// Falls back to the symbol table of the running kernel when there is no
+/// debuginfo, giving frames like `btrfs_sync_file+0x1a4`.
+pub struct Kallsyms {
+    /// Symbols of each object in its own address space, sorted by address.
+    symbols: HashMap<String, Vec<(u64, String)>>,
+}
+
+impl Kallsyms {
+    /// Reads a copy of `/proc/kallsyms`. Symbols are moved into the address
+    /// spaces the other backends work in: by `vmlinux_offset` for vmlinux,
+    /// and relative to the base in `modules` for modules.
+    pub fn load(
+        kallsyms: &Path,
+        modules: &HashMap<String, u64>,
+        vmlinux_offset: i64,
+    ) -> Result<Self> {
+        let file = File::open(kallsyms).map_err(|e| TraceError::io(kallsyms, e))?;
+        let mut symbols: HashMap<String, Vec<(u64, String)>> = HashMap::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| TraceError::io(kallsyms, e))?;
+            let mut parts = line.split_whitespace();
+            let (Some(addr), Some(kind), Some(name)) = (parts.next(), parts.next(), parts.next())
+            else {
+                return Err(TraceError::malformed(kallsyms, i as u64 + 1, "too few fields"));
+            };
+            // Only code can show up in stack traces.
+            if !matches!(kind, "t" | "T" | "w" | "W") {
+                continue;
+            }
+            let addr = u64::from_str_radix(addr, 16).map_err(|e| {
+                TraceError::malformed(kallsyms, i as u64 + 1, format!("address: {}", e))
+            })?;
+            let (object, addr) = match parts.next() {
+                Some(module) => {
+                    let module = module.trim_start_matches('[').trim_end_matches(']');
+                    let Some(base) = modules.get(module) else {
+                        continue;
+                    };
+                    (module, addr.wrapping_sub(*base))
+                }
+                None => ("vmlinux", (addr as i64).wrapping_add(vmlinux_offset) as u64),
+            };
+            symbols
+                .entry(object.to_owned())
+                .or_default()
+                .push((addr, name.to_owned()));
+        }
+        for symbols in symbols.values_mut() {
+            symbols.sort_unstable();
+        }
+        Ok(Self { symbols })
+    }
+}
+
+impl Symbolize for Kallsyms {
+    fn symbolize(
+        &mut self,
+        object: &KernelObject,
+        addrs: &[u64],
+    ) -> Result<HashMap<u64, Vec<Frame>>> {
+        let Some(symbols) = self.symbols.get(&object.name) else {
+            return Ok(HashMap::new());
+        };
+        Ok(addrs
+            .iter()
+            .filter_map(|&addr| {
+                let i = symbols.partition_point(|(start, _)| *start <= addr);
+                let (start, name) = symbols.get(i.checked_sub(1)?)?;
+                let frame = Frame {
+                    function: format!("{}+{:#x}", name, addr - start),
+                    location: format!("[{}]", object.name),
+                };
+                Some((addr, vec![frame]))
+            })
+            .collect())
+    }
+}
+
+/// Formats `frames` the way stack.csv stores a resolved address.
+pub fn format_frames(frames: &[Frame]) -> String {
+    frames
+        .iter()
+        .map(|frame| format!("{}\t{}", frame.function, frame.location))
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    use super::{
+        parse_addr2line, parse_llvm_symbolizer, Kallsyms, KernelObject, Symbolize, Symbolizer,
+    };
+
+    /// `outer` starts at 0, with `inner` inlined into it from 2 to 7; see
+    /// `tests/fixtures/inline.c`.
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/inline.o");
+    const KALLSYMS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/kallsyms");
+
+    fn functions(frames: &[super::Frame]) -> Vec<&str> {
+        frames.iter().map(|frame| frame.function.as_str()).collect()
+    }
+
+    #[test]
+    fn resolves_addresses() {
+        let mut symbolizer = Symbolizer::new();
+        let frames = symbolizer.resolve(Path::new(FIXTURE), 0).unwrap();
+        assert_eq!(functions(&frames), ["outer"]);
+        assert!(frames[0].location.ends_with("inline.c:16"), "{:?}", frames);
+    }
+
+    #[test]
+    fn resolves_inlined_frames_innermost_first() {
+        let mut symbolizer = Symbolizer::new();
+        let frames = symbolizer.resolve(Path::new(FIXTURE), 4).unwrap();
+        assert_eq!(functions(&frames), ["inner", "outer"]);
+        assert!(frames[0].location.ends_with("inline.c:11"), "{:?}", frames);
+        assert!(frames[1].location.ends_with("inline.c:18"), "{:?}", frames);
+    }
+
+    #[test]
+    fn unknown_addresses_get_an_unknown_frame() {
+        let object = KernelObject {
+            name: "inline".to_owned(),
+            path: FIXTURE.into(),
+        };
+        let resolved = Symbolizer::new().symbolize(&object, &[4, 0x1000]).unwrap();
+        assert_eq!(functions(&resolved[&4]), ["inner", "outer"]);
+        assert_eq!(functions(&resolved[&0x1000]), ["??"]);
+        assert_eq!(resolved[&0x1000][0].location, "??:0");
+    }
+
+    #[test]
+    fn missing_objects_are_left_unresolved() {
+        let mut symbolizer = Symbolizer::new();
+        assert!(symbolizer.resolve(Path::new("no/such/vmlinux"), 0).is_err());
+
+        let object = KernelObject {
+            name: "btrfs".to_owned(),
+            path: "no/such/btrfs.ko".into(),
+        };
+        let resolved = symbolizer.symbolize(&object, &[0x1000]).unwrap();
+        assert!(resolved.is_empty());
+        // Other objects still resolve.
+        let object = KernelObject {
+            name: "inline".to_owned(),
+            path: FIXTURE.into(),
+        };
+        let resolved = symbolizer.symbolize(&object, &[4]).unwrap();
+        assert_eq!(functions(&resolved[&4]), ["inner", "outer"]);
+    }
+
+    #[test]
+    fn parses_addr2line_output() {
+        let output = "0x4\ninner\n./inline.c:11\nouter\n./inline.c:18\n0x1000\n??\n??:0\n";
+        let resolved = parse_addr2line(Path::new(FIXTURE), output).unwrap();
+        assert_eq!(resolved.len(), 2);
+        assert_eq!(functions(&resolved[&4]), ["inner", "outer"]);
+        assert_eq!(resolved[&4][1].location, "./inline.c:18");
+        assert_eq!(functions(&resolved[&0x1000]), ["??"]);
+    }
+
+    #[test]
+    fn rejects_truncated_addr2line_output() {
+        assert!(parse_addr2line(Path::new(FIXTURE), "0x4\ninner\n").is_err());
+        assert!(parse_addr2line(Path::new(FIXTURE), "inner\n./inline.c:11\n").is_err());
+    }
+
+    #[test]
+    fn parses_llvm_symbolizer_output() {
+        let output = br#"[
+            {"Address": "0x4", "ModuleName": "inline.o", "Symbol": [
+                {"Column": 12, "FileName": "./inline.c", "FunctionName": "inner", "Line": 11},
+                {"Column": 9, "FileName": "./inline.c", "FunctionName": "outer", "Line": 18}
+            ]},
+            {"Address": "0x1000", "ModuleName": "inline.o", "Symbol": [{}]}
+        ]"#;
+        let resolved = parse_llvm_symbolizer(Path::new(FIXTURE), output).unwrap();
+        assert_eq!(functions(&resolved[&4]), ["inner", "outer"]);
+        assert_eq!(resolved[&4][0].location, "./inline.c:11:12");
+        assert_eq!(functions(&resolved[&0x1000]), [""]);
+
+        assert!(parse_llvm_symbolizer(Path::new(FIXTURE), b"[{\"Address\": \"4\"}]").is_err());
+        assert!(parse_llvm_symbolizer(Path::new(FIXTURE), b"").is_err());
+    }
+
+    #[test]
+    fn kallsyms_gives_the_nearest_symbol_below() {
+        let modules = HashMap::from([("btrfs".to_owned(), 0xffff_ffff_c000_0000)]);
+        let mut kallsyms = Kallsyms::load(Path::new(KALLSYMS), &modules, 0x1000).unwrap();
+        let object = |name: &str| KernelObject {
+            name: name.to_owned(),
+            path: name.into(),
+        };
+
+        let resolved = kallsyms
+            .symbolize(
+                &object("vmlinux"),
+                &[
+                    0xffff_ffff_8100_0000,
+                    0xffff_ffff_8100_1104,
+                    0xffff_ffff_8100_2010,
+                ],
+            )
+            .unwrap();
+        // Before the first symbol, once moved by the offset.
+        assert!(!resolved.contains_key(&0xffff_ffff_8100_0000));
+        assert_eq!(
+            resolved[&0xffff_ffff_8100_1104][0].function,
+            "do_one_initcall+0x4"
+        );
+        assert_eq!(resolved[&0xffff_ffff_8100_1104][0].location, "[vmlinux]");
+        // Data symbols are skipped.
+        assert_eq!(
+            resolved[&0xffff_ffff_8100_2010][0].function,
+            "do_one_initcall+0xf10"
+        );
+
+        let resolved = kallsyms
+            .symbolize(&object("btrfs"), &[0x1010, 0x2000])
+            .unwrap();
+        assert_eq!(resolved[&0x1010][0].function, "btrfs_sync_file+0x10");
+        assert_eq!(resolved[&0x2000][0].function, "btrfs_write_check+0x0");
+
+        // Modules that were not loaded are left out.
+        assert!(
+            kallsyms
+                .symbolize(&object("ext4"), &[0x1000])
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -1,9 +1,22 @@
 #![feature(let_chains)]
+use std::path::PathBuf;
+
 use app::TemplateApp;
+use clap::Parser;
 
 mod app;
+mod grid;
+
+#[derive(Parser)]
+#[command(about = "Explore block-layer traces produced by trace-process")]
+struct Cli {
+    /// Trace directories, each containing bio.json, stack.csv and syscall.json
+    traces: Vec<PathBuf>,
+}
+
 fn main() -> eframe::Result {
     env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
+    let cli = Cli::parse();
 
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
@@ -14,6 +27,6 @@ fn main() -> eframe::Result {
     eframe::run_native(
         "eframe template",
         native_options,
-        Box::new(|cc| Ok(Box::new(TemplateApp::new(cc)))),
+        Box::new(|cc| Ok(Box::new(TemplateApp::new(cc, &cli.traces)))),
     )
 }
@@ -1,139 +1,126 @@
 #![feature(iter_array_chunks)]
 #![feature(btree_cursors)]
 
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
 use csv::{ReaderBuilder, WriterBuilder};
 use itertools::Itertools;
-use proc_modules::Module;
-use serde::Deserialize;
+use pipeline::{Pipeline, Record};
+use report::Report;
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
-use std::io::BufRead;
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
 use std::ops::Bound;
-use std::path::Path;
-use std::process::Command;
-
-use trace_explorer::trace::{Bio, Syscall, SyscallKind, Write};
-
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "PascalCase")]
-struct Symbol {
-    column: u32,
-    discriminator: u32,
-    file_name: String,
-    function_name: String,
-    line: u32,
-    start_address: String,
-    start_file_name: String,
-    start_line: u32,
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use trace_explorer::error::{Result, TraceError};
+use trace_explorer::data::TraceData;
+use trace_explorer::snapshot::{self, KernelSnapshot, KALLSYMS_FILE, SNAPSHOT_FILE};
+use trace_explorer::symbolize::{
+    self, Addr2line, Kallsyms, KernelObject, LlvmSymbolizer, Symbolize, Symbolizer,
+};
+
+mod pipeline;
+mod report;
+
+#[derive(Parser)]
+#[command(about = "Turn raw tracer logs into traces for trace-explorer")]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "PascalCase")]
-struct LlvmSymbolizerItem {
-    address: String,
-    module_name: String,
-    symbol: Vec<Symbol>,
+#[derive(Subcommand)]
+enum Commands {
+    /// Symbolize stack traces and pair up the events of a tracer log
+    Process(ProcessArgs),
+    /// Record the layout of the running kernel; run this when capturing a trace
+    Snapshot(SnapshotArgs),
+    /// Print latency, flush and IO statistics of a processed trace
+    Report(ReportArgs),
 }
 
-impl LlvmSymbolizerItem {
-    fn address_(&self) -> u64 {
-        let stripped = self.address.strip_prefix("0x").unwrap();
-        u64::from_str_radix(stripped, 16).unwrap()
-    }
+#[derive(Args)]
+struct ReportArgs {
+    /// Directory with the bio.json, syscall.json and stack.csv of the trace
+    #[arg(default_value = ".")]
+    trace: PathBuf,
+
+    /// Print the report as JSON instead of tables
+    #[arg(long)]
+    json: bool,
+
+    /// Number of stack traces to list
+    #[arg(long, default_value_t = 10)]
+    top: usize,
 }
 
-fn parse_trace(bio_list: &mut Vec<Bio>, syscall_list: &mut Vec<Syscall>) {
-    let file = File::open("output.csv").unwrap();
-    let mut reader = ReaderBuilder::new().flexible(true).from_reader(file);
+#[derive(Args)]
+struct SnapshotArgs {
+    /// Directory to write kernel.json and kallsyms to
+    #[arg(short, long, default_value = ".")]
+    output_dir: PathBuf,
+}
 
-    'outer: for result in reader.records() {
-        let record = result.unwrap();
-        let event_type = &record[0];
+#[derive(Args)]
+struct ProcessArgs {
+    /// Log written by the tracer, or `-` for stdin
+    #[arg(short, long, default_value = "log.csv")]
+    input: PathBuf,
 
-        if event_type.contains("Attaching") {
-            continue;
-        }
+    /// Directory to write stack.csv, bio.json and syscall.json to
+    #[arg(short, long, default_value = ".")]
+    output_dir: PathBuf,
 
-        let tid: u64 = record[1].parse().unwrap();
-        let timestamp: i64 = record[2].parse().unwrap();
-
-        if event_type == "bio_queue" {
-            let bio = Bio {
-                offset: record[3].parse().unwrap(),
-                size: record[4].parse().unwrap(),
-                is_metadata: record[5].contains("M"),
-                is_flush: record[5].contains("F"),
-                is_write: record[5].contains("W"),
-                start: timestamp,
-                end: None,
-                stack_trace: record[6].parse().unwrap(),
-            };
-            bio_list.push(bio);
-        } else if event_type == "bio_rq_complete" {
-            if let Ok(offset) = record[3].parse() {
-                let size: u64 = record[4].parse().unwrap();
-                if size == 0 {
-                    for bio in bio_list.iter_mut().rev().take(32) {
-                        if bio.offset == offset && bio.is_flush {
-                            bio.end = Some(timestamp);
-                            continue 'outer;
-                        }
-                    }
-                }
-
-                for bio in bio_list.iter_mut().rev().take(32) {
-                    if bio.end.is_none()
-                        && bio.offset >= offset
-                        && bio.offset + bio.size <= offset + size
-                    {
-                        bio.end = Some(timestamp);
-                    }
-                }
-            } else {
-                continue;
-            }
-        } else if event_type == "fsync_start" {
-            let syscall = Syscall {
-                kind: SyscallKind::Fsync,
-                start: timestamp,
-                end: None,
-                tid,
-                stats: None,
-            };
-            syscall_list.push(syscall);
-        } else if event_type == "fsync_end" {
-            let syscall = syscall_list
-                .iter_mut()
-                .rev()
-                .find(|x| x.tid == tid && x.end.is_none());
-            if let Some(syscall) = syscall {
-                syscall.end = Some(timestamp);
-            }
-        } else if event_type == "write_start" {
-            let syscall = Syscall {
-                kind: SyscallKind::Write(Write {
-                    offset: record[4].parse().unwrap(),
-                    bytes: record[5].parse().unwrap(),
-                }),
-                start: timestamp,
-                end: None,
-                tid,
-                stats: None,
-            };
-            syscall_list.push(syscall);
-        } else if event_type == "write_end" {
-            let syscall = syscall_list.iter_mut().rev().find(|x| {
-                assert!(matches!(x.kind, SyscallKind::Write(_)));
-                x.tid == tid && x.end.is_none()
-            });
-            if let Some(syscall) = syscall {
-                syscall.end = Some(timestamp);
-            }
-        }
-    }
+    /// Kernel image with debuginfo matching the traced kernel
+    #[arg(long)]
+    vmlinux: Option<PathBuf>,
+
+    /// Directory to look for `<module>.ko` in [default: the directory of --vmlinux]
+    #[arg(long)]
+    module_dir: Option<PathBuf>,
+
+    /// Directory with the kernel.json and kallsyms of the traced kernel
+    /// [default: the directory of --input]
+    #[arg(long)]
+    snapshot: Option<PathBuf>,
+
+    /// Directories with the files the traced syscalls touched, to name them
+    #[arg(long = "files")]
+    file_dirs: Vec<PathBuf>,
+
+    /// How to turn stack trace addresses into functions
+    #[arg(long, value_enum, default_value_t = Backend::Dwarf)]
+    symbolizer: Backend,
+
+    /// Report malformed records and skip them instead of stopping at the first one
+    #[arg(short, long)]
+    keep_going: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Backend {
+    /// Read the DWARF of vmlinux and the modules directly
+    Dwarf,
+    /// Run GNU addr2line
+    Addr2line,
+    /// Run llvm-symbolizer
+    LlvmSymbolizer,
+    /// Only use the kernel symbol table, for when there is no debuginfo
+    Kallsyms,
 }
 
-fn process_stack_traces(stack_traces: HashMap<String, usize>) {
+fn process_stack_traces(
+    stack_traces: HashMap<String, usize>,
+    kernel: &KernelSnapshot,
+    kallsyms: &Path,
+    backend: Backend,
+    vmlinux: Option<&Path>,
+    module_dir: &Path,
+    output: &Path,
+) -> Result<()> {
     let mut stack_traces: Vec<(String, usize)> = stack_traces.into_iter().collect();
     stack_traces.sort_by_key(|x| x.1);
 
@@ -152,39 +139,99 @@ fn process_stack_traces(stack_traces: HashMap<String, usize>) {
                 .collect::<Vec<_>>()
         })
         .collect();
-    dbg!(&stack_traces);
 
-    let curr_text_addr = kernel_text_addr();
-    dbg!(curr_text_addr);
-    let vmlinux_text_addr = vmlinux_text_addr(Path::new("/home/mike/tmp/modules/vmlinux"));
-    dbg!(vmlinux_text_addr);
-    let offset = (vmlinux_text_addr as i64 - curr_text_addr as i64) as i64;
-    resolve_addr(&mut addr_to_loc, offset);
+    let curr_text_addr = kernel.stext;
+    // Without a vmlinux, the kallsyms backend works with the addresses of the
+    // traced kernel.
+    let offset = match vmlinux {
+        Some(vmlinux) => {
+            check_build_id(kernel, vmlinux)?;
+            let vmlinux_text_addr = symbolize::section_address(vmlinux, ".text")?
+                .ok_or_else(|| TraceError::symbolize(vmlinux, "no .text section"))?;
+            vmlinux_text_addr as i64 - curr_text_addr as i64
+        }
+        None => 0,
+    };
+
+    let modules: BTreeMap<u64, String> = kernel
+        .modules
+        .iter()
+        .map(|module| (module.base, module.name.clone()))
+        .collect();
+    let mut symbolizer: Box<dyn Symbolize> = match backend {
+        Backend::Dwarf => Box::new(Symbolizer::new()),
+        Backend::Addr2line => Box::new(Addr2line),
+        Backend::LlvmSymbolizer => Box::new(LlvmSymbolizer),
+        Backend::Kallsyms => {
+            let bases = kernel
+                .modules
+                .iter()
+                .map(|module| (module.name.clone(), module.base))
+                .collect();
+            Box::new(Kallsyms::load(kallsyms, &bases, offset)?)
+        }
+    };
+    let vmlinux = vmlinux.unwrap_or(Path::new("vmlinux"));
+    resolve_addr(
+        symbolizer.as_mut(),
+        &modules,
+        &mut addr_to_loc,
+        offset,
+        vmlinux,
+        module_dir,
+    )?;
 
-    let mut writer = WriterBuilder::new()
-        .flexible(false)
-        .from_writer(std::fs::File::create("stack.csv").unwrap());
+    let file = File::create(output).map_err(|e| TraceError::io(output, e))?;
+    let mut writer = WriterBuilder::new().flexible(false).from_writer(file);
 
     for (i, s) in stack_traces.iter().enumerate() {
         let record = [
             i.to_string(),
             s.iter()
-                .map(|x| addr_to_loc[x].as_ref().unwrap())
+                .map(|x| match &addr_to_loc[x] {
+                    Some(loc) => loc.clone(),
+                    None => format!("{:#x}\t??:0", x),
+                })
                 .join("\n"),
         ];
-        writer.write_record(record).unwrap();
+        writer
+            .write_record(record)
+            .map_err(|e| TraceError::csv(output, e))?;
     }
+    Ok(())
 }
 
-fn resolve_addr(addr_to_line: &mut HashMap<u64, Option<String>>, vmlinux_offset: i64) {
-    let modules: BTreeMap<u64, Module> = proc_modules::ModuleIter::new()
-        .unwrap()
-        .filter_map(|m| {
-            let m = m.unwrap();
-            Some((m.base?, m))
-        })
-        .collect();
+/// Warns if `vmlinux` is not the kernel the trace was captured on, which
+/// would give wrong frames.
+fn check_build_id(kernel: &KernelSnapshot, vmlinux: &Path) -> Result<()> {
+    let Some(expected) = &kernel.build_id else {
+        return Ok(());
+    };
+    match snapshot::vmlinux_build_id(vmlinux)? {
+        Some(actual) if actual == *expected => {}
+        Some(actual) => eprintln!(
+            "warning: {} has build-id {}, but the trace was captured on {} with build-id {}",
+            vmlinux.display(),
+            actual,
+            kernel.release,
+            expected
+        ),
+        None => eprintln!(
+            "warning: {} has no build-id to check against the traced kernel",
+            vmlinux.display()
+        ),
+    }
+    Ok(())
+}
 
+fn resolve_addr(
+    symbolizer: &mut dyn Symbolize,
+    modules: &BTreeMap<u64, String>,
+    addr_to_line: &mut HashMap<u64, Option<String>>,
+    vmlinux_offset: i64,
+    vmlinux: &Path,
+    module_dir: &Path,
+) -> Result<()> {
     let mut addr_per_module = HashMap::new();
     let mut vmlinux_addr = HashSet::new();
 
@@ -192,7 +239,7 @@ fn resolve_addr(addr_to_line: &mut HashMap<u64, Option<String>>, vmlinux_offset:
         let mut module = modules.upper_bound(Bound::Included(addr));
         if let Some((base, module)) = module.prev() {
             let (_, set) = addr_per_module
-                .entry(module.module.clone())
+                .entry(module.clone())
                 .or_insert_with(|| (-(*base as i64), HashSet::new()));
             set.insert(*addr);
         } else {
@@ -202,147 +249,178 @@ fn resolve_addr(addr_to_line: &mut HashMap<u64, Option<String>>, vmlinux_offset:
     addr_per_module.insert("vmlinux".to_string(), (vmlinux_offset, vmlinux_addr));
 
     for (module, (base, addrs)) in &addr_per_module {
-        let path = format!(
-            "/home/mike/tmp/modules/{}",
-            if module == "vmlinux" {
-                "vmlinux".to_string()
+        let object = KernelObject {
+            name: module.clone(),
+            path: if module == "vmlinux" {
+                vmlinux.to_owned()
             } else {
-                format!("{}.ko", module)
+                module_dir.join(format!("{}.ko", module))
+            },
+        };
+        let addrs: Vec<u64> = addrs.iter().map(|x| (*x as i64 + base) as u64).collect();
+        // Skipping the object leaves its addresses as `??:0` in stack.csv,
+        // which is better than no stack.csv at all.
+        let resolved = match symbolizer.symbolize(&object, &addrs) {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                eprintln!("warning: {}, leaving its addresses unresolved", e);
+                continue;
             }
-        );
-        let output = Command::new("addr2line")
-            .arg("--functions")
-            .arg("--inlines")
-            .arg("--addresses")
-            .arg("-e")
-            .arg(&path)
-            .args(addrs.iter().map(|x| format!("{:#x}", (*x as i64) + base)))
-            .output()
-            .unwrap();
-        let output = String::from_utf8(output.stdout).unwrap();
-        dbg!(&output);
-        let mut lines = output.lines().peekable();
-        while let Some(addr) = lines.next() {
-            let addr_striped = addr.strip_prefix("0x").unwrap();
-            let addr = u64::from_str_radix(addr_striped, 16).unwrap();
+        };
+        for (addr, frames) in resolved {
             let addr = (addr as i64 - base) as u64;
-
-            let mut loc = String::new();
-            while let Some(next_line) = lines.peek() {
-                if next_line.starts_with("0x") {
-                    break;
-                }
-                let function = lines.next().unwrap();
-                let file = lines.next().unwrap();
-                loc.push_str(&format!("{}\t{}\n", function, file));
+            if let Some(loc) = addr_to_line.get_mut(&addr) {
+                *loc = Some(symbolize::format_frames(&frames));
             }
-            loc.pop(); // remove the last newline
-            *addr_to_line.get_mut(&addr).unwrap() = Some(loc);
-        }
-        /*
-        let output = Command::new("llvm-symbolizer")
-            .arg("--output-style=JSON")
-            .arg("--obj")
-            .arg(&path)
-            .args(addrs.iter().map(|x| format!("{:#x}", (*x as i64) + base)))
-            .output()
-            .unwrap();
-        dbg!(&output);
-        let items: Vec<LlvmSymbolizerItem> = serde_json::from_slice(&output.stdout).unwrap();
-        dbg!(&items);
-        for i in items {
-            let addr = ((i.address_() as i64) - base) as u64;
-            let loc = addr_to_line.get_mut(&addr).unwrap();
-            let s = i
-                .symbol
-                .iter()
-                .map(|x| {
-                    format!(
-                        "{}\t{}:{}:{}",
-                        x.function_name, x.start_file_name, x.start_line, x.column
-                    )
-                })
-                .join("\n");
-            dbg!(&s);
-            loc.replace(s);
         }
-        */
     }
+    Ok(())
+}
 
-    dbg!(&addr_per_module);
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Commands::Process(args) => process(args),
+        Commands::Snapshot(args) => take_snapshot(args),
+        Commands::Report(args) => report(args),
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
 }
 
-fn vmlinux_text_addr(vmlinux: &Path) -> u64 {
-    // use readelf to get the address of .text section
-    let output = std::process::Command::new("readelf")
-        .arg("-S")
-        .arg(vmlinux)
-        .output()
-        .unwrap();
-    for line in String::from_utf8(output.stdout).unwrap().lines() {
-        if line.contains(".text") {
-            let parts: Vec<_> = line.split_whitespace().collect();
-            return u64::from_str_radix(parts[4], 16).unwrap();
+fn process(args: ProcessArgs) -> Result<()> {
+    if args.vmlinux.is_none() && args.symbolizer != Backend::Kallsyms {
+        Cli::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--vmlinux is required unless --symbolizer is kallsyms",
+            )
+            .exit();
+    }
+    let module_dir = match args.module_dir {
+        Some(dir) => dir,
+        None => args
+            .vmlinux
+            .as_deref()
+            .and_then(Path::parent)
+            .map(Path::to_owned)
+            .unwrap_or_default(),
+    };
+    let snapshot_dir = match args.snapshot {
+        Some(dir) => dir,
+        None => args.input.parent().map(Path::to_owned).unwrap_or_default(),
+    };
+    let snapshot_file = snapshot_dir.join(SNAPSHOT_FILE);
+    let (kernel, kallsyms) = if snapshot_file.exists() {
+        (
+            KernelSnapshot::load(&snapshot_file)?,
+            snapshot_dir.join(KALLSYMS_FILE),
+        )
+    } else {
+        eprintln!(
+            "warning: no {}, assuming the trace was captured on the running kernel",
+            snapshot_file.display()
+        );
+        (KernelSnapshot::capture()?, PathBuf::from("/proc/kallsyms"))
+    };
+
+    let input: Box<dyn Read> = if args.input == Path::new("-") {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(File::open(&args.input).map_err(|e| TraceError::io(&args.input, e))?)
+    };
+    let mut reader = ReaderBuilder::new().flexible(true).from_reader(input);
+
+    let mut file_paths = HashMap::new();
+    for dir in &args.file_dirs {
+        index_files(dir, &mut file_paths)?;
+    }
+    let mut pipeline = Pipeline::create(&args.output_dir, &kernel.block_devices, file_paths)?;
+    for result in reader.records() {
+        let record = result.map_err(|e| TraceError::csv(&args.input, e))?;
+        if let Err(e) = pipeline.push(&Record::new(&args.input, &record)) {
+            if !args.keep_going {
+                return Err(e);
+            }
+            eprintln!("skipping {}", e);
         }
     }
-    unreachable!()
+    let finished = pipeline.finish()?;
+    if finished.unmatched_queues > 0 {
+        eprintln!("{} bios were never completed", finished.unmatched_queues);
+    }
+    if finished.unmatched_completions > 0 {
+        eprintln!(
+            "{} completions did not belong to any queued bio",
+            finished.unmatched_completions
+        );
+    }
+    if finished.unmatched_events > 0 {
+        eprintln!(
+            "{} merge, insert or issue events did not belong to any queued bio",
+            finished.unmatched_events
+        );
+    }
+
+    process_stack_traces(
+        finished.stack_traces,
+        &kernel,
+        &kallsyms,
+        args.symbolizer,
+        args.vmlinux.as_deref(),
+        &module_dir,
+        &args.output_dir.join("stack.csv"),
+    )
 }
 
-fn kernel_text_addr() -> u64 {
-    // read file /proc/kallsyms to get the address of stext
-    let file = File::open("/proc/kallsyms").unwrap();
-    let reader = std::io::BufReader::new(file);
-    for line in reader.lines() {
-        let line = line.unwrap();
-        let mut parts = line.split_whitespace();
-        let addr = parts.next().unwrap();
-        parts.next().unwrap();
-        let name = parts.next().unwrap();
-        if name == "_stext" {
-            return u64::from_str_radix(addr, 16).unwrap();
+/// Adds the files under `dir` to `paths`, by the major and minor number of
+/// their device and their inode. Files that cannot be read are left out.
+fn index_files(dir: &Path, paths: &mut HashMap<(u32, u32, u64), String>) -> Result<()> {
+    let entries = std::fs::read_dir(dir).map_err(|e| TraceError::io(dir, e))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            if let Err(e) = index_files(&path, paths) {
+                eprintln!("warning: {}", e);
+            }
+            continue;
         }
+        // glibc's encoding of `dev_t`
+        let dev = metadata.dev();
+        let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+        let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+        paths
+            .entry((major as u32, minor as u32, metadata.ino()))
+            .or_insert_with(|| path.display().to_string());
     }
-    unreachable!()
+    Ok(())
 }
 
-fn main() {
-    let file = File::open("log.csv").unwrap();
-    let mut reader = ReaderBuilder::new().flexible(true).from_reader(file);
-    let writer = std::fs::File::create("output.csv").unwrap();
-    let mut writer = WriterBuilder::new().flexible(true).from_writer(writer);
+fn take_snapshot(args: SnapshotArgs) -> Result<()> {
+    let kernel = KernelSnapshot::capture()?;
+    kernel.save(&args.output_dir.join(SNAPSHOT_FILE))?;
 
-    let mut stack_traces: HashMap<String, usize> = HashMap::new();
-    let mut stack_trace_id = 0;
+    let kallsyms = Path::new("/proc/kallsyms");
+    let copy = args.output_dir.join(KALLSYMS_FILE);
+    std::fs::copy(kallsyms, &copy).map_err(|e| TraceError::io(&copy, e))?;
+    Ok(())
+}
 
-    for result in reader.records() {
-        let record = result.unwrap();
-        let new_record = record.iter().map(|x| {
-            if x.contains('\n') {
-                let curr_stack_trace = x.to_owned();
-                stack_traces
-                    .entry(curr_stack_trace)
-                    .or_insert_with(|| {
-                        let ret = stack_trace_id;
-                        stack_trace_id += 1;
-                        ret
-                    })
-                    .to_string()
-            } else {
-                x.to_owned()
-            }
-        });
-        writer.write_record(new_record).unwrap();
+fn report(args: ReportArgs) -> Result<()> {
+    let mut data = TraceData::load(&args.trace)?;
+    let report = Report::new(&mut data, args.top);
+    if args.json {
+        let json = serde_json::to_string_pretty(&report)
+            .map_err(|e| TraceError::json(Path::new("-"), e))?;
+        println!("{}", json);
+    } else {
+        print!("{}", report.to_text());
     }
-    drop(writer);
-
-    process_stack_traces(stack_traces);
-
-    let mut bio_list = vec![];
-    let mut syscall_list = vec![];
-    parse_trace(&mut bio_list, &mut syscall_list);
-    // write bio_list to a json file
-    let bio_file = File::create("bio.json").unwrap();
-    serde_json::to_writer(bio_file, &bio_list).unwrap();
-    let syscall_file = File::create("syscall.json").unwrap();
-    serde_json::to_writer(syscall_file, &syscall_list).unwrap();
+    Ok(())
 }
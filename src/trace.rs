@@ -1,21 +1,141 @@
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// A block device.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Dev {
+    pub major: u32,
+    pub minor: u32,
+    /// Name of the device node, e.g. `nvme0n1p2`, if it is known.
+    pub name: Option<String>,
+}
+
+impl Dev {
+    /// Splits a `dev_t` in the kernel's internal encoding.
+    pub fn from_kernel(dev: u32) -> Self {
+        Self {
+            major: dev >> 20,
+            minor: dev & 0xfffff,
+            name: None,
+        }
+    }
+}
+
+impl fmt::Display for Dev {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{} ({}:{})", name, self.major, self.minor),
+            None => write!(f, "{}:{}", self.major, self.minor),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Bio {
+    /// Traces from before devices were recorded are from a single device.
+    #[serde(default)]
+    pub dev: Dev,
     pub offset: u64,
     pub size: u64,
     pub is_metadata: bool,
     pub is_flush: bool,
     pub is_write: bool,
+    /// When the bio was queued.
     pub start: i64,
+    /// When the bio was merged into an existing request.
+    #[serde(default)]
+    pub merge: Option<i64>,
+    /// When the request of the bio was inserted into the I/O scheduler.
+    #[serde(default)]
+    pub insert: Option<i64>,
+    /// When the request of the bio was dispatched to the device.
+    #[serde(default)]
+    pub issue: Option<i64>,
+    /// When the request of the bio completed.
     pub end: Option<i64>,
     pub stack_trace: usize,
+    /// The task that queued the bio.
+    #[serde(default)]
+    pub pid: u64,
+    #[serde(default)]
+    pub tid: u64,
+    #[serde(default)]
+    pub comm: String,
+    /// The CPU the bio was queued on.
+    #[serde(default)]
+    pub cpu: u32,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl Bio {
+    /// The lifecycle events of the bio that were traced, in order.
+    pub fn phases(&self) -> Vec<(&'static str, i64)> {
+        let mut phases: Vec<_> = [
+            ("queue", Some(self.start)),
+            ("merge", self.merge),
+            ("insert", self.insert),
+            ("issue", self.issue),
+            ("complete", self.end),
+        ]
+        .into_iter()
+        .filter_map(|(name, time)| Some((name, time?)))
+        .collect();
+        phases.sort_by_key(|&(_, time)| time);
+        phases
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub enum SyscallKind {
+    #[default]
     Fsync,
-    Write(Write)
+    Fdatasync,
+    SyncFileRange(SyncFileRange),
+    Msync,
+    Syncfs,
+    Sync,
+    Write(Write),
+    Read(Read),
+    Pread(Read),
+    Preadv(Read),
+    /// Reads into the page cache only.
+    Readahead(Read),
+}
+
+impl SyscallKind {
+    /// Name of the syscall, as in the event types of the tracer log.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SyscallKind::Fsync => "fsync",
+            SyscallKind::Fdatasync => "fdatasync",
+            SyscallKind::SyncFileRange(_) => "sync_file_range",
+            SyscallKind::Msync => "msync",
+            SyscallKind::Syncfs => "syncfs",
+            SyscallKind::Sync => "sync",
+            SyscallKind::Write(_) => "write",
+            SyscallKind::Read(_) => "read",
+            SyscallKind::Pread(_) => "pread",
+            SyscallKind::Preadv(_) => "preadv",
+            SyscallKind::Readahead(_) => "readahead",
+        }
+    }
+
+    /// How many bytes the syscall asked for, for syscalls on a range.
+    pub fn bytes(&self) -> Option<u64> {
+        match self {
+            SyscallKind::Write(write) => Some(write.bytes),
+            SyscallKind::Read(read)
+            | SyscallKind::Pread(read)
+            | SyscallKind::Preadv(read)
+            | SyscallKind::Readahead(read) => Some(read.bytes),
+            SyscallKind::SyncFileRange(range) => Some(range.nbytes),
+            SyscallKind::Fsync
+            | SyscallKind::Fdatasync
+            | SyscallKind::Msync
+            | SyscallKind::Syncfs
+            | SyscallKind::Sync => None,
+        }
+    }
 }
 
 
@@ -26,17 +146,70 @@ pub struct Write{
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SyncFileRange {
+    pub offset: u64,
+    /// 0 means up to the end of the file.
+    pub nbytes: u64,
+    /// `SYNC_FILE_RANGE_*`
+    pub flags: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Read {
+    pub offset: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Syscall {
     pub kind: SyscallKind,
     pub start: i64,
     pub end: Option<i64>,
+    #[serde(default)]
+    pub pid: u64,
     pub tid: u64,
+    /// Name of the process.
+    #[serde(default)]
+    pub comm: String,
+    /// The CPU the syscall was entered on.
+    #[serde(default)]
+    pub cpu: u32,
+    /// The file the syscall was on, for syscalls that take a file.
+    #[serde(default)]
+    pub file: Option<FileId>,
     pub stats: Option<SyscallStats>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileId {
+    pub fd: i32,
+    pub inode: Option<Inode>,
+    /// Where the file was found after tracing, if it was looked for.
+    pub path: Option<String>,
+}
+
+impl fmt::Display for FileId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.path, &self.inode) {
+            (Some(path), _) => write!(f, "{}", path),
+            (None, Some(inode)) => write!(f, "inode {} on {}", inode.ino, inode.dev),
+            (None, None) => write!(f, "fd {}", self.fd),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Inode {
+    /// The device of the file system.
+    pub dev: Dev,
+    pub ino: u64,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SyscallStats {
     pub write_sectors: u64,
+    #[serde(default)]
+    pub read_sectors: u64,
     pub flushes: u64,
     pub frac_io_time: f64,
 }
\ No newline at end of file
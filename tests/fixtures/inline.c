@@ -0,0 +1,19 @@
+/*
+ * Fixture for the symbolize tests: a relocatable object, like a kernel
+ * module, with a function inlined into another. Built with
+ *
+ *     gcc -c -g -O2 -fno-asynchronous-unwind-tables -fdebug-prefix-map=$PWD=. \
+ *         inline.c -o inline.o
+ */
+
+static inline __attribute__((always_inline)) int inner(volatile int *x)
+{
+	return *x * 3 + 1;
+}
+
+int outer(volatile int *x)
+{
+	int y = *x;
+
+	return inner(x) + y;
+}